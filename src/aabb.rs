@@ -0,0 +1,181 @@
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::vec::Point3;
+
+// Axis-aligned bounding box, stored as one interval per axis
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Aabb {
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        Self { x, y, z }
+    }
+
+    pub const fn empty() -> Self {
+        Self {
+            x: Interval::empty(),
+            y: Interval::empty(),
+            z: Interval::empty(),
+        }
+    }
+
+    // Box containing all of space, for unbounded objects like planes
+    pub fn universe() -> Self {
+        Self {
+            x: Interval::default(),
+            y: Interval::default(),
+            z: Interval::default(),
+        }
+    }
+
+    // Box with the two points as opposite corners, in any order
+    pub fn from_points(a: Point3, b: Point3) -> Self {
+        Self {
+            x: Interval::new(a.x.min(b.x), a.x.max(b.x)),
+            y: Interval::new(a.y.min(b.y), a.y.max(b.y)),
+            z: Interval::new(a.z.min(b.z), a.z.max(b.z)),
+        }
+    }
+
+    // Smallest box containing both boxes
+    pub fn enclosing(a: &Aabb, b: &Aabb) -> Self {
+        Self {
+            x: Interval::enclosing(&a.x, &b.x),
+            y: Interval::enclosing(&a.y, &b.y),
+            z: Interval::enclosing(&a.z, &b.z),
+        }
+    }
+
+    // Widen the axes thinner than `delta`, so that flat objects still get hit by the slab test
+    pub fn pad_to_minimums(&self, delta: f64) -> Self {
+        let pad = |interval: Interval| {
+            if interval.size() < delta {
+                interval.expand(delta)
+            } else {
+                interval
+            }
+        };
+        Self {
+            x: pad(self.x),
+            y: pad(self.y),
+            z: pad(self.z),
+        }
+    }
+
+    pub fn axis(&self, n: usize) -> Interval {
+        match n {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Invalid axis index"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.size() < 0. || self.y.size() < 0. || self.z.size() < 0.
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        !self.is_empty()
+            && [self.x, self.y, self.z]
+                .iter()
+                .any(|interval| interval.lower.is_infinite() || interval.upper.is_infinite())
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        if x >= y && x >= z {
+            return 0;
+        }
+        if y >= z {
+            return 1;
+        } else {
+            return 2;
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            0.5 * (self.x.lower + self.x.upper),
+            0.5 * (self.y.lower + self.y.upper),
+            0.5 * (self.z.lower + self.z.upper),
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        return 2. * (x * y + y * z + z * x);
+    }
+
+    // Slab test: intersect the ray with the three pairs of planes
+    pub fn hit(&self, ray: &Ray, ray_t: Interval) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let mut ray_t = ray_t;
+        let origin = ray.origin.to_array();
+        let direction = ray.direction.to_array();
+        for axis in 0..3 {
+            let interval = self.axis(axis);
+            let inverse_direction = 1. / direction[axis];
+
+            let t0 = (interval.lower - origin[axis]) * inverse_direction;
+            let t1 = (interval.upper - origin[axis]) * inverse_direction;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            ray_t.lower = ray_t.lower.max(t0);
+            ray_t.upper = ray_t.upper.min(t1);
+            if ray_t.upper <= ray_t.lower {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Vec3;
+
+    #[test]
+    fn test_enclosing() {
+        let a = Aabb::from_points(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
+        let b = Aabb::from_points(Point3::new(2., -1., 0.5), Point3::new(3., 0., 0.));
+        let both = Aabb::enclosing(&a, &b);
+        assert_eq!((both.x.lower, both.x.upper), (0., 3.));
+        assert_eq!((both.y.lower, both.y.upper), (-1., 1.));
+        assert_eq!((both.z.lower, both.z.upper), (0., 1.));
+        assert_eq!(both.longest_axis(), 0);
+        assert!(Aabb::enclosing(&Aabb::empty(), &a).surface_area() == 6.);
+    }
+
+    #[test]
+    fn test_hit() {
+        let aabb = Aabb::from_points(Point3::new(-1., -1., -1.), Point3::new(1., 1., 1.));
+        let towards = Ray::new(Point3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let away = Ray::new(Point3::new(0., 0., 5.), Vec3::new(0., 0., 1.));
+        let beside = Ray::new(Point3::new(2., 0., 5.), Vec3::new(0., 0., -1.));
+        assert!(aabb.hit(&towards, Interval::new(0., f64::INFINITY)));
+        assert!(!aabb.hit(&towards, Interval::new(0., 3.)));
+        assert!(!aabb.hit(&away, Interval::new(0., f64::INFINITY)));
+        assert!(!aabb.hit(&beside, Interval::new(0., f64::INFINITY)));
+        assert!(!Aabb::empty().hit(&towards, Interval::new(0., f64::INFINITY)));
+        assert!(Aabb::universe().hit(&away, Interval::new(0., f64::INFINITY)));
+        assert!(Aabb::universe().is_unbounded() && !aabb.is_unbounded());
+        assert!(!Aabb::empty().is_unbounded());
+    }
+}
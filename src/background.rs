@@ -0,0 +1,456 @@
+use crate::color::Color;
+use crate::rng::SampleRng;
+use crate::vec::Vec3;
+
+use rand::Rng;
+use std::f64::consts::PI;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::path::Path;
+
+// Radiance coming from infinitely far away, for rays that don't hit anything
+pub trait Background: Send + Sync {
+    fn value(&self, direction: &Vec3) -> Color;
+
+    // Sample a unit direction proportionally to the radiance, along with its solid angle pdf.
+    // Backgrounds that can't be importance sampled return None.
+    fn sample(&self, _rng: &mut SampleRng) -> Option<(Vec3, f64)> {
+        None
+    }
+
+    // Solid angle pdf of `sample` returning this direction
+    fn pdf(&self, _direction: &Vec3) -> f64 {
+        0.
+    }
+}
+
+pub struct SolidBackground {
+    pub color: Color,
+}
+
+impl SolidBackground {
+    pub fn new(color: Color) -> Self {
+        SolidBackground { color }
+    }
+}
+
+impl Background for SolidBackground {
+    fn value(&self, _direction: &Vec3) -> Color {
+        self.color
+    }
+}
+
+// Vertical blend between two colors, from looking straight down to straight up
+pub struct GradientBackground {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+impl GradientBackground {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        GradientBackground { bottom, top }
+    }
+}
+
+impl Default for GradientBackground {
+    // The good old sky
+    fn default() -> Self {
+        GradientBackground::new(Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for GradientBackground {
+    fn value(&self, direction: &Vec3) -> Color {
+        let unit_direction = direction.normalize();
+        let a = 0.5 * (unit_direction.y + 1.0);
+        return (1. - a) * self.bottom + a * self.top;
+    }
+}
+
+// Latitude-longitude HDR image surrounding the scene. The center of the image is towards -z,
+// the top row is straight up.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    // Cumulative distributions used for importance sampling: over the rows, then over the
+    // pixels of each row
+    marginal_cdf: Vec<f64>,
+    conditional_cdfs: Vec<Vec<f64>>,
+    // Probability of picking each pixel
+    pixel_probabilities: Vec<f64>,
+}
+
+// Normalize the running sums of `weights` into a CDF, uniform if all weights are zero
+fn cdf(weights: &[f64]) -> Vec<f64> {
+    let mut sums: Vec<f64> = weights
+        .iter()
+        .scan(0., |sum, weight| {
+            *sum += weight;
+            Some(*sum)
+        })
+        .collect();
+    let total = *sums.last().unwrap_or(&0.);
+    let n = sums.len();
+    for (i, sum) in sums.iter_mut().enumerate() {
+        *sum = if total > 0. {
+            *sum / total
+        } else {
+            (i + 1) as f64 / n as f64
+        };
+    }
+    return sums;
+}
+
+// Index of the first CDF entry above u
+fn sample_cdf(cdf: &[f64], u: f64) -> usize {
+    cdf.partition_point(|&value| value <= u).min(cdf.len() - 1)
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert!(width > 0 && height > 0, "Empty environment map");
+        assert_eq!(pixels.len(), width * height);
+
+        // Rows near the poles cover a smaller solid angle
+        let weights: Vec<f64> = (0..height)
+            .flat_map(|y| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                let pixels = &pixels;
+                (0..width).map(move |x| pixels[y * width + x].luminance().max(0.) * sin_theta)
+            })
+            .collect();
+
+        let row_weights: Vec<f64> = weights.chunks(width).map(|row| row.iter().sum()).collect();
+        let marginal_cdf = cdf(&row_weights);
+        let conditional_cdfs: Vec<Vec<f64>> = weights.chunks(width).map(cdf).collect();
+
+        let total: f64 = row_weights.iter().sum();
+        let pixel_probabilities = if total > 0. {
+            weights.iter().map(|weight| weight / total).collect()
+        } else {
+            vec![1. / (width * height) as f64; width * height]
+        };
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            marginal_cdf,
+            conditional_cdfs,
+            pixel_probabilities,
+        }
+    }
+
+    pub fn load_hdr<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let file = std::fs::File::open(path)?;
+        let (width, height, pixels) = read_hdr(&mut BufReader::new(file))?;
+        return Ok(EnvironmentMap::new(width, height, pixels));
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        for pixel in &mut self.pixels {
+            *pixel = factor * *pixel;
+        }
+    }
+
+    fn pixel_index(&self, direction: &Vec3) -> (usize, f64) {
+        let direction = direction.normalize();
+        let theta = direction.y.clamp(-1., 1.).acos();
+        let phi = direction.x.atan2(-direction.z);
+        let u = 0.5 + phi / (2. * PI);
+        let v = theta / PI;
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        return (y * self.width + x, theta.sin());
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, direction: &Vec3) -> Color {
+        self.pixels[self.pixel_index(direction).0]
+    }
+
+    fn sample(&self, rng: &mut SampleRng) -> Option<(Vec3, f64)> {
+        let y = sample_cdf(&self.marginal_cdf, rng.gen());
+        let x = sample_cdf(&self.conditional_cdfs[y], rng.gen());
+
+        // Uniform position within the pixel
+        let u = (x as f64 + rng.gen::<f64>()) / self.width as f64;
+        let v = (y as f64 + rng.gen::<f64>()) / self.height as f64;
+        let phi = 2. * PI * (u - 0.5);
+        let theta = PI * v;
+        let direction = Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        );
+
+        let pdf = self.pdf(&direction);
+        if pdf <= 0. {
+            return None;
+        }
+        return Some((direction, pdf));
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let (index, sin_theta) = self.pixel_index(direction);
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        // Each pixel covers (2 pi / width) * (pi / height) in (phi, theta)
+        let pixel_area = 2. * PI * PI / (self.width * self.height) as f64;
+        return self.pixel_probabilities[index] / (pixel_area * sin_theta);
+    }
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::black();
+    }
+    let scale = 2f64.powi(rgbe[3] as i32 - 136);
+    Color::new(
+        rgbe[0] as f64 * scale,
+        rgbe[1] as f64 * scale,
+        rgbe[2] as f64 * scale,
+    )
+}
+
+// Read one scanline of new-style run length encoded RGBE, each channel encoded separately
+fn read_rle_scanline<R: Read>(
+    reader: &mut R,
+    width: usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<(), std::io::Error> {
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let count = count[0] as usize;
+            if count > 128 {
+                // A run of the same value
+                let count = count - 128;
+                if x + count > width {
+                    return Err(invalid_data("Bad HDR scanline run length"));
+                }
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value[0];
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("Bad HDR scanline length"));
+                }
+                let mut values = vec![0u8; count];
+                reader.read_exact(&mut values)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    return Ok(());
+}
+
+// Radiance RGBE (.hdr) image, returns its width, height and pixels in linear RGB
+pub fn read_hdr<R: BufRead>(reader: &mut R) -> Result<(usize, usize, Vec<Color>), std::io::Error> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("Not a Radiance HDR file"));
+    }
+
+    // Header lines up to an empty line
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("Unexpected end of HDR header"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_data("Unsupported HDR pixel format"));
+            }
+        }
+    }
+
+    // Only the standard orientation is supported
+    line.clear();
+    reader.read_line(&mut line)?;
+    let resolution: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match resolution.as_slice() {
+        ["-Y", height, "+X", width] => (
+            height
+                .parse::<usize>()
+                .map_err(|_| invalid_data("Bad HDR height"))?,
+            width
+                .parse::<usize>()
+                .map_err(|_| invalid_data("Bad HDR width"))?,
+        ),
+        _ => return Err(invalid_data("Unsupported HDR resolution line")),
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid_data("Empty HDR image"));
+    }
+    if width.checked_mul(height).is_none() {
+        return Err(invalid_data("HDR image is too large"));
+    }
+
+    // The size comes from the header, so buffers grow with the pixels actually read rather
+    // than being allocated up front
+    let mut pixels = Vec::new();
+    let mut scanline = Vec::new();
+    for _ in 0..height {
+        let mut first = [0u8; 4];
+        reader.read_exact(&mut first)?;
+        scanline.clear();
+        let is_rle = (8..0x8000).contains(&width) && first[0] == 2 && first[1] == 2;
+        if is_rle {
+            if ((first[2] as usize) << 8 | first[3] as usize) != width {
+                return Err(invalid_data("HDR scanline width mismatch"));
+            }
+            scanline.resize(width, [0u8; 4]);
+            read_rle_scanline(reader, width, &mut scanline)?;
+        } else {
+            // Flat pixels
+            scanline.push(first);
+            for _ in 1..width {
+                let mut pixel = [0u8; 4];
+                reader.read_exact(&mut pixel)?;
+                scanline.push(pixel);
+            }
+        }
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_color(rgbe)));
+    }
+
+    return Ok((width, height, pixels));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::io::Cursor;
+
+    fn hdr_header(width: usize, height: usize) -> Vec<u8> {
+        format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            height, width
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_read_flat_hdr() {
+        let mut data = hdr_header(2, 1);
+        // 1.0 is 128 * 2^(129 - 136)
+        data.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+        let (width, height, pixels) = read_hdr(&mut Cursor::new(data)).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, vec![Color::new(1., 0.5, 0.), Color::black()]);
+    }
+
+    #[test]
+    fn test_read_rle_hdr() {
+        let width = 10;
+        let mut data = hdr_header(width, 1);
+        data.extend_from_slice(&[2, 2, 0, width as u8]);
+        // Red: a run of 10 values
+        data.extend_from_slice(&[128 + 10, 128]);
+        // Green: 3 literal values then a run of 7
+        data.extend_from_slice(&[3, 0, 64, 128, 128 + 7, 32]);
+        // Blue: a run of 10 zeros
+        data.extend_from_slice(&[128 + 10, 0]);
+        // Exponent: a run of 10
+        data.extend_from_slice(&[128 + 10, 129]);
+
+        let (_, _, pixels) = read_hdr(&mut Cursor::new(data)).unwrap();
+        assert_eq!(pixels.len(), width);
+        assert_eq!(pixels[0], Color::new(1., 0., 0.));
+        assert_eq!(pixels[2], Color::new(1., 1., 0.));
+        assert_eq!(pixels[9], Color::new(1., 0.25, 0.));
+    }
+
+    #[test]
+    fn test_read_hdr_errors() {
+        assert!(read_hdr(&mut Cursor::new(b"P3\n1 1\n255\n".to_vec())).is_err());
+
+        let mut truncated = hdr_header(4, 4);
+        truncated.extend_from_slice(&[128, 64, 0, 129]);
+        assert!(read_hdr(&mut Cursor::new(truncated)).is_err());
+
+        // Sizes that can't be allocated are errors, not aborts
+        for (width, height) in [(usize::MAX / 2, 3), (1 << 40, 1 << 20), (1, usize::MAX)] {
+            let mut huge = hdr_header(width, height);
+            huge.extend_from_slice(&[128, 64, 0, 129]);
+            assert!(read_hdr(&mut Cursor::new(huge)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_gradient_background() {
+        let sky = GradientBackground::default();
+        assert_eq!(sky.value(&Vec3::new(0., -2., 0.)), sky.bottom);
+        assert_eq!(sky.value(&Vec3::new(0., 3., 0.)), sky.top);
+    }
+
+    #[test]
+    fn test_environment_map_lookup() {
+        // 4x2 map, each pixel a different color
+        let pixels: Vec<Color> = (0..8).map(|i| Color::new(i as f64, 0., 0.)).collect();
+        let map = EnvironmentMap::new(4, 2, pixels);
+        // Looking forward and slightly up hits the top row, right of the center
+        assert_eq!(map.value(&Vec3::new(0.1, 0.1, -1.)).x, 2.);
+        // Looking backward and down hits the bottom row, on the edges
+        assert_eq!(map.value(&Vec3::new(0.1, -0.1, 1.)).x, 7.);
+        assert_eq!(map.value(&Vec3::new(-0.1, -0.1, 1.)).x, 4.);
+    }
+
+    #[test]
+    fn test_environment_map_importance_sampling() {
+        // A dim map with one bright pixel
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+        let bright = 3 * width + 5;
+        pixels[bright] = Color::new(100., 100., 100.);
+        let map = EnvironmentMap::new(width, height, pixels);
+
+        let mut rng = SampleRng::seed_from_u64(0);
+        let n_samples = 10_000;
+        let mut n_bright = 0;
+        let mut pdf_integral = 0.;
+        for _ in 0..n_samples {
+            let (direction, pdf) = map.sample(&mut rng).unwrap();
+            assert!((direction.length() - 1.).abs() < 1e-9);
+            assert!((pdf - map.pdf(&direction)).abs() <= 1e-9 * pdf);
+            if map.pixel_index(&direction).0 == bright {
+                n_bright += 1;
+            }
+            // Estimate the integral of the radiance over the sphere
+            pdf_integral += map.value(&direction).luminance() / pdf;
+        }
+        assert!(n_bright as f64 / n_samples as f64 > 0.8);
+
+        // Compare with the integral computed pixel by pixel
+        let expected: f64 = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                let area = 2. * PI * PI * sin_theta / (width * height) as f64;
+                map.pixels[y * width + x].luminance() * area
+            })
+            .sum();
+        let estimate = pdf_integral / n_samples as f64;
+        assert!((estimate - expected).abs() < 0.05 * expected);
+    }
+}
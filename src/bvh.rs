@@ -0,0 +1,245 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::interval::Interval;
+use crate::ray::Ray;
+
+// Bounding volume hierarchy, split with the surface area heuristic
+pub struct BvhNode {
+    left: Box<dyn Hittable>,
+    right: Option<Box<dyn Hittable>>,
+    bbox: Aabb,
+    // Objects without a finite box, like planes, are kept out of the tree and always tested.
+    // Only the root has any.
+    unbounded: HittableList,
+}
+
+impl BvhNode {
+    pub fn new(list: HittableList) -> Self {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = list
+            .objects
+            .into_iter()
+            .partition(|object| !object.bounding_box().is_unbounded());
+        let mut root = Self::build(bounded);
+        root.unbounded.objects = unbounded;
+        return root;
+    }
+
+    fn build(mut objects: Vec<Box<dyn Hittable>>) -> Self {
+        match objects.len() {
+            0 => {
+                return BvhNode {
+                    left: Box::new(HittableList::new()),
+                    right: None,
+                    bbox: Aabb::empty(),
+                    unbounded: HittableList::new(),
+                }
+            }
+            1 => {
+                let left = objects.pop().unwrap();
+                let bbox = left.bounding_box();
+                return BvhNode {
+                    left,
+                    right: None,
+                    bbox,
+                    unbounded: HittableList::new(),
+                };
+            }
+            _ => (),
+        }
+
+        let boxes: Vec<Aabb> = objects.iter().map(|object| object.bounding_box()).collect();
+        let bbox = boxes
+            .iter()
+            .fold(Aabb::empty(), |acc, b| Aabb::enclosing(&acc, b));
+
+        // Sort along the axis where the centroids are the most spread out
+        let centroid_bounds = boxes.iter().fold(Aabb::empty(), |acc, b| {
+            let centroid = b.centroid();
+            Aabb::enclosing(&acc, &Aabb::from_points(centroid, centroid))
+        });
+        let axis = centroid_bounds.longest_axis();
+        let mut order: Vec<usize> = (0..objects.len()).collect();
+        order.sort_by(|&a, &b| {
+            let a = boxes[a].axis(axis);
+            let b = boxes[b].axis(axis);
+            (a.lower + a.upper).total_cmp(&(b.lower + b.upper))
+        });
+
+        let split = Self::sah_split(&order.iter().map(|&i| boxes[i]).collect::<Vec<_>>());
+
+        let mut objects: Vec<Option<Box<dyn Hittable>>> = objects.into_iter().map(Some).collect();
+        let mut sorted: Vec<Box<dyn Hittable>> =
+            order.iter().map(|&i| objects[i].take().unwrap()).collect();
+        let right_objects = sorted.split_off(split);
+
+        BvhNode {
+            left: Self::build_child(sorted),
+            right: Some(Self::build_child(right_objects)),
+            bbox,
+            unbounded: HittableList::new(),
+        }
+    }
+
+    fn build_child(mut objects: Vec<Box<dyn Hittable>>) -> Box<dyn Hittable> {
+        if objects.len() == 1 {
+            return objects.pop().unwrap();
+        }
+        return Box::new(Self::build(objects));
+    }
+
+    // Index splitting the sorted boxes in two non-empty halves with the lowest SAH cost
+    fn sah_split(sorted_boxes: &[Aabb]) -> usize {
+        let n = sorted_boxes.len();
+
+        // right_areas[i] is the area of the box enclosing sorted_boxes[i..]
+        let mut right_areas = vec![0.; n];
+        let mut right_box = Aabb::empty();
+        for i in (1..n).rev() {
+            right_box = Aabb::enclosing(&right_box, &sorted_boxes[i]);
+            right_areas[i] = right_box.surface_area();
+        }
+
+        let mut best_split = n / 2;
+        let mut best_cost = f64::INFINITY;
+        let mut left_box = Aabb::empty();
+        for split in 1..n {
+            left_box = Aabb::enclosing(&left_box, &sorted_boxes[split - 1]);
+            let cost =
+                left_box.surface_area() * split as f64 + right_areas[split] * (n - split) as f64;
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+        return best_split;
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let unbounded_record = self.unbounded.hit(ray, ray_t);
+        let ray_t = Interval::new(
+            ray_t.lower,
+            unbounded_record.map_or(ray_t.upper, |record| record.t),
+        );
+        if !self.bbox.hit(ray, ray_t) {
+            return unbounded_record;
+        }
+
+        let left_record = self.left.hit(ray, ray_t);
+        let closest_so_far = left_record.map_or(ray_t.upper, |record| record.t);
+        let right_record = self
+            .right
+            .as_ref()
+            .and_then(|right| right.hit(ray, Interval::new(ray_t.lower, closest_so_far)));
+
+        return right_record.or(left_record).or(unbounded_record);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::enclosing(&self.bbox, &self.unbounded.bounding_box())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MATERIAL_CONCRETE;
+    use crate::quad::Plane;
+    use crate::sphere::Sphere;
+    use crate::vec::{Point3, Vec3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_spheres(rng: &mut StdRng, n: usize) -> HittableList {
+        let mut list = HittableList::new();
+        for _ in 0..n {
+            let center = Point3::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            );
+            let radius = rng.gen_range(0.1..1.5);
+            list.add(Box::new(Sphere::new(
+                center,
+                radius,
+                MATERIAL_CONCRETE.clone(),
+            )));
+        }
+        return list;
+    }
+
+    #[test]
+    fn test_bvh_matches_linear_list() {
+        for n in [0, 1, 2, 3, 10, 500] {
+            let list = random_spheres(&mut StdRng::seed_from_u64(n as u64), n);
+            let bvh = BvhNode::new(random_spheres(&mut StdRng::seed_from_u64(n as u64), n));
+
+            let mut rng = StdRng::seed_from_u64(42);
+            let mut n_hits = 0;
+            for _ in 0..2000 {
+                let origin = Point3::new(
+                    rng.gen_range(-15.0..15.0),
+                    rng.gen_range(-15.0..15.0),
+                    rng.gen_range(-15.0..15.0),
+                );
+                let direction = Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                );
+                let ray = Ray::new(origin, direction);
+                let ray_t = Interval::new(0.001, f64::INFINITY);
+
+                let expected = list.hit(&ray, ray_t);
+                let actual = bvh.hit(&ray, ray_t);
+                assert_eq!(expected.is_some(), actual.is_some());
+                if let (Some(expected), Some(actual)) = (expected, actual) {
+                    assert_eq!(expected.t, actual.t);
+                    assert_eq!(expected.p, actual.p);
+                    assert_eq!(expected.normal, actual.normal);
+                    assert_eq!(expected.front_face, actual.front_face);
+                    n_hits += 1;
+                }
+            }
+            if n >= 10 {
+                assert!(n_hits > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bvh_bounding_box_encloses_list() {
+        let list = random_spheres(&mut StdRng::seed_from_u64(7), 50);
+        let expected = list.bounding_box();
+        let bvh = BvhNode::new(list);
+        let actual = bvh.bounding_box();
+        for axis in 0..3 {
+            assert_eq!(expected.axis(axis).lower, actual.axis(axis).lower);
+            assert_eq!(expected.axis(axis).upper, actual.axis(axis).upper);
+        }
+    }
+
+    #[test]
+    fn test_bvh_with_planes() {
+        let mut list = random_spheres(&mut StdRng::seed_from_u64(3), 20);
+        list.add(Box::new(Plane::new(
+            Point3::new(0., -5., 0.),
+            Vec3::new(0., 1., 0.),
+            MATERIAL_CONCRETE.clone(),
+        )));
+        let bvh = BvhNode::new(list);
+        assert!(bvh.bounding_box().is_unbounded());
+
+        // Far away from the spheres, only the plane can be hit
+        let ray = Ray::new(Point3::new(1000., 0., 1000.), Vec3::new(0., -1., 0.));
+        let record = bvh.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert_eq!(record.t, 5.);
+
+        // Spheres in front of the plane hide it
+        let ray = Ray::new(Point3::new(0., 100., 0.), Vec3::new(0., -1., 0.));
+        let record = bvh.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert!(record.p.y > -5.);
+    }
+}
@@ -1,27 +1,68 @@
-use crate::color::{write_color, Color};
+use crate::background::{Background, GradientBackground};
+use crate::checkpoint::Checkpoint;
+use crate::framebuffer::Framebuffer;
 use crate::hittable::Hittable;
-use crate::interval::Interval;
+use crate::hittable_list::LightList;
+use crate::image_writer::ImageFormat;
+use crate::integrator::{Integrator, PathTracer};
+use crate::progress::{NoProgress, ProgressCounter, ProgressReporter};
 use crate::ray::Ray;
+use crate::rng::{hash_bytes, hash_values, sample_rng, SampleRng};
+use crate::stats::{PathStats, RunningVariance};
+use crate::tiles::{tiles, Tile, TileOrder};
 use crate::utils;
 use crate::vec::{Point3, Vec3};
 use rand::Rng;
 
 use rayon::prelude::*;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 pub struct Camera {
     pub aspect_ratio: f64,
     pub image_width: usize,
+    // The most samples a pixel gets. With adaptive sampling, pixels stop after
+    // `min_samples_per_pixel` samples once their noise is under `noise_threshold`.
     pub num_samples_per_pixel: i32,
-    pub max_depth: i32,
+    pub min_samples_per_pixel: i32,
+    // Standard error of the pixel brightness after gamma correction, on a scale where white
+    // is 1. 0 turns adaptive sampling off.
+    pub noise_threshold: f64,
+    // Samples are taken in passes of this many samples per pixel, all pixels improving together
+    pub samples_per_pass: i32,
+    // Passes are split into square tiles of this many pixels a side, rendered in parallel
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub progress: Box<dyn ProgressReporter>,
+    // Set it to stop the render after the tiles being rendered
+    pub cancel: Arc<AtomicBool>,
+    // Hard limit on the number of bounces of a path, 0 for none. Paths cut short are missing
+    // light, the image gets darker.
+    pub max_depth: u32,
+    // Bounces before Russian roulette starts ending paths that don't carry much light
+    pub russian_roulette_depth: u32,
     pub vfov: f64,
     pub lookfrom: Point3,
     pub lookat: Point3,
     pub v_up: Vec3,
     pub defocus_angle: f64,
     pub focus_dist: f64,
-
+    // Rays are sent at uniformly random times in [shutter_open, shutter_close]
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    pub background: Box<dyn Background>,
+    // Computes the value of each sample, the path tracer unless debugging
+    pub integrator: Box<dyn Integrator>,
+    // Emitters sampled directly at every diffuse bounce. They must also be part of the world.
+    pub lights: LightList,
+    // Renders with the same seed are identical, whatever the number of threads
+    pub seed: u64,
+    // Of the scene description the camera comes from, so that checkpoints of other scenes
+    // are refused
+    pub scene_hash: u64,
+
+    // Exact height asked for with `set_image_size`, otherwise it follows from the aspect ratio
+    requested_height: Option<usize>,
     image_height: usize,
     center: Point3,
     pixel_delta_u: Vec3,
@@ -34,6 +75,8 @@ pub struct Camera {
     // Defocus disk horizontal and vertical radii
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    // Of the last render
+    path_stats: PathStats,
 }
 
 impl Default for Camera {
@@ -43,14 +86,30 @@ impl Default for Camera {
             image_width: 100,
             image_height: 100,
             num_samples_per_pixel: 100,
-            max_depth: 10,
+            min_samples_per_pixel: 16,
+            noise_threshold: 0.,
+            samples_per_pass: 16,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            progress: Box::new(NoProgress),
+            cancel: Arc::new(AtomicBool::new(false)),
+            max_depth: 0,
+            russian_roulette_depth: 3,
             vfov: 90.0,
             lookfrom: Point3::new(0.0, 0.0, 0.0),
             lookat: Point3::new(0.0, 0.0, -1.0),
             v_up: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.,
             focus_dist: 10.,
-
+            shutter_open: 0.,
+            shutter_close: 0.,
+            background: Box::new(GradientBackground::default()),
+            integrator: Box::new(PathTracer),
+            lights: LightList::new(),
+            seed: 0,
+            scene_hash: 0,
+
+            requested_height: None,
             // These will be initialized in initialize
             center: Point3::new(0., 0., 0.),
             pixel_delta_u: Vec3::new(0., 0., 0.),
@@ -63,6 +122,7 @@ impl Default for Camera {
 
             defocus_disk_u: Vec3::default(),
             defocus_disk_v: Vec3::default(),
+            path_stats: PathStats::default(),
         };
         camera.initialize();
         return camera;
@@ -70,10 +130,23 @@ impl Default for Camera {
 }
 
 impl Camera {
+    pub fn set_image_size(&mut self, width: usize, height: usize) {
+        self.image_width = width;
+        self.aspect_ratio = width as f64 / height as f64;
+        self.requested_height = Some(height);
+    }
+
+    pub fn image_height(&self) -> usize {
+        if let Some(height) = self.requested_height {
+            return height;
+        }
+        return ((self.image_width as f64 / self.aspect_ratio) as usize).max(1);
+    }
+
     fn initialize(&mut self) {
         // Camera
 
-        self.image_height = ((self.image_width as f64 / self.aspect_ratio) as usize).max(1);
+        self.image_height = self.image_height();
 
         let theta = utils::degrees_to_radians(self.vfov);
         let h = (0.5 * theta).tan();
@@ -107,41 +180,12 @@ impl Camera {
         self.defocus_disk_v = self.v * defocus_radius;
     }
 
-    fn sample_from_defocus_disk(&self, rng: &mut rand::rngs::ThreadRng) -> Vec3 {
+    fn sample_from_defocus_disk(&self, rng: &mut SampleRng) -> Vec3 {
         let p = Vec3::random_in_unit_disk(rng);
         return self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v);
     }
 
-    fn ray_color(
-        &self,
-        rng: &mut rand::rngs::ThreadRng,
-        ray: &Ray,
-        world: &dyn Hittable,
-        depth: i32,
-    ) -> Color {
-        if depth <= 0 {
-            // return black
-            return Color::new(0.0, 0.0, 0.0);
-        }
-
-        if let Some(hit_record) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) {
-            if let Some((attenuation, scattered_ray)) =
-                hit_record.material.scatter(rng, ray, &hit_record)
-            {
-                return attenuation * self.ray_color(rng, &scattered_ray, world, depth - 1);
-            } else {
-                // If no scatter than return black
-                return Color::new(0.0, 0.0, 0.0);
-            }
-        } else {
-            // Background color
-            let unit_direction = ray.direction.normalize();
-            let a = 0.5 * (unit_direction.y + 1.0);
-            return (1. - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0);
-        }
-    }
-
-    fn get_ray(&self, x: usize, y: usize, rng: &mut rand::rngs::ThreadRng) -> Ray {
+    fn get_ray(&self, x: usize, y: usize, rng: &mut SampleRng) -> Ray {
         let mut pixel_center =
             self.pixel00_location + x as f64 * self.pixel_delta_u + y as f64 * self.pixel_delta_v;
 
@@ -152,64 +196,674 @@ impl Camera {
             self.sample_from_defocus_disk(rng)
         };
         let ray_direction = pixel_center - ray_origin;
-        let ray = Ray::new(ray_origin, ray_direction);
+        // Instantaneous shutters don't consume random numbers, still scenes render the same
+        let ray_time = if self.shutter_close > self.shutter_open {
+            rng.gen_range(self.shutter_open..self.shutter_close)
+        } else {
+            self.shutter_open
+        };
+        let ray = Ray::with_time(ray_origin, ray_direction, ray_time);
         return ray;
     }
 
-    fn sample_pixel_from_square(&self, rng: &mut rand::rngs::ThreadRng) -> Vec3 {
+    fn sample_pixel_from_square(&self, rng: &mut SampleRng) -> Vec3 {
         let x = rng.gen_range(-0.5..0.5);
         let y = rng.gen_range(-0.5..0.5);
 
         return x * self.pixel_delta_u + y * self.pixel_delta_v;
     }
 
-    fn render_line(&self, world: &dyn Hittable, row_idx: usize, row: &mut [f64]) {
-        let mut rng = rand::thread_rng();
+    // Whether a pixel has had enough samples, given the spread of its luminance
+    fn converged(&self, variance: &RunningVariance) -> bool {
+        if self.noise_threshold <= 0. || variance.count < self.min_samples_per_pixel.max(2) as u32 {
+            return false;
+        }
+        // Samples that all came out the same, e.g. because a small light was missed every
+        // time, say nothing about the noise
+        if variance.m2 <= 0. {
+            return false;
+        }
+        // Gamma correction takes the square root, which scales errors by 1 / (2 sqrt(mean)).
+        // The floor keeps black pixels from needing no noise at all.
+        let display_error = variance.standard_error() / (2. * variance.mean.max(1e-4).sqrt());
+        return display_error <= self.noise_threshold;
+    }
 
-        for x in 0..self.image_width {
-            let mut color = Color::default();
-            for _ in 0..self.num_samples_per_pixel {
-                let ray = self.get_ray(x, row_idx, &mut rng);
-                color += self.ray_color(&mut rng, &ray, world, self.max_depth);
+    // Take samples until each pixel of the tile has `target_samples`, or converges. Returns
+    // the paths traced and the number of rays, shadow rays included.
+    fn render_tile(
+        &self,
+        world: &dyn Hittable,
+        tile: &Tile,
+        pixels: &mut Framebuffer,
+        target_samples: u32,
+    ) -> (PathStats, u64) {
+        let mut stats = PathStats::default();
+        let mut camera_rays = 0;
+        for y in 0..tile.height {
+            for x in 0..tile.width {
+                let (image_x, image_y) = (tile.x + x, tile.y + y);
+                for sample_index in pixels.sample_count(x, y)..target_samples {
+                    if self.converged(pixels.variance(x, y)) {
+                        break;
+                    }
+                    let mut rng = sample_rng(self.seed, image_x, image_y, sample_index as u64);
+                    let ray = self.get_ray(image_x, image_y, &mut rng);
+                    let color = self
+                        .integrator
+                        .ray_color(self, &mut rng, &ray, world, &mut stats);
+                    pixels.add_sample(x, y, color);
+                    camera_rays += 1;
+                }
             }
-            row[3 * x..3 * (x + 1)].copy_from_slice(&color.to_array());
+        }
+        let rays = camera_rays + stats.total_bounces + stats.shadow_rays;
+        return (stats, rays);
+    }
+
+    // Render the tiles in order, as many at a time as there are threads. Returns the paths
+    // traced, and whether all the tiles got done before the render was cancelled.
+    fn render_pass(
+        &self,
+        world: &dyn Hittable,
+        framebuffer: &mut Framebuffer,
+        tiles: &[Tile],
+        target_samples: u32,
+        progress: &ProgressCounter,
+    ) -> (PathStats, bool) {
+        // Each thread takes the next tile when it is done with one, rather than rayon
+        // splitting the tiles, which would not keep to their order
+        let next_tile = AtomicUsize::new(0);
+        let tiles_done = AtomicUsize::new(0);
+        let framebuffer = Mutex::new(framebuffer);
+        let stats = (0..rayon::current_num_threads())
+            .into_par_iter()
+            .map(|_| {
+                let mut stats = PathStats::default();
+                while !self.cancel.load(Ordering::Relaxed) {
+                    let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let mut pixels = framebuffer.lock().unwrap().tile(tile);
+                    let (tile_stats, rays) =
+                        self.render_tile(world, tile, &mut pixels, target_samples);
+                    framebuffer.lock().unwrap().set_tile(tile, &pixels);
+                    tiles_done.fetch_add(1, Ordering::Relaxed);
+                    self.progress.tile_done(&progress.tile_done(rays));
+                    stats = stats.merge(tile_stats);
+                }
+                return stats;
+            })
+            .reduce(PathStats::default, PathStats::merge);
+        return (stats, tiles_done.into_inner() == tiles.len());
+    }
+
+    // Render the scene in memory, without tone mapping or writing anything to disk
+    pub fn render_to_buffer(&mut self, world: &dyn Hittable) -> Framebuffer {
+        let mut checkpoint = self.new_checkpoint();
+        self.render_passes(world, &mut checkpoint, |_| ())
+            .expect("New checkpoints fit their camera");
+        return checkpoint.framebuffer;
+    }
+
+    // Hash of the scene and of the settings that change the samples. The number of samples
+    // and how they are split into passes and tiles are left out, renders can be resumed with
+    // other ones.
+    pub fn settings_hash(&self) -> u64 {
+        let mut values = vec![
+            // The bits of the hashes are kept as they are
+            f64::from_bits(self.scene_hash),
+            f64::from_bits(hash_bytes(self.integrator.name().as_bytes())),
+            self.min_samples_per_pixel as f64,
+            self.noise_threshold,
+            self.max_depth as f64,
+            self.russian_roulette_depth as f64,
+            self.vfov,
+            self.defocus_angle,
+            self.focus_dist,
+            self.shutter_open,
+            self.shutter_close,
+        ];
+        for vector in [self.lookfrom, self.lookat, self.v_up] {
+            values.extend([vector.x, vector.y, vector.z]);
+        }
+        values.extend(self.integrator.settings());
+        return hash_values(&values);
+    }
+
+    // State of a render that has not started yet
+    pub fn new_checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            seed: self.seed,
+            settings_hash: self.settings_hash(),
+            samples_per_pixel: 0,
+            framebuffer: Framebuffer::new(self.image_width, self.image_height()),
         }
     }
 
-    pub fn render(&mut self, world: &dyn Hittable, image_path: &str) {
+    // Whether a render with this camera can pick up from the checkpoint: same image size, seed,
+    // scene and sampling settings
+    pub fn check_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), String> {
+        let framebuffer = &checkpoint.framebuffer;
+        if (framebuffer.width, framebuffer.height) != (self.image_width, self.image_height()) {
+            return Err(format!(
+                "the checkpoint is {}x{}, the image {}x{}",
+                framebuffer.width,
+                framebuffer.height,
+                self.image_width,
+                self.image_height()
+            ));
+        }
+        if checkpoint.seed != self.seed {
+            return Err(format!(
+                "the checkpoint has seed {}, the render {}",
+                checkpoint.seed, self.seed
+            ));
+        }
+        if checkpoint.settings_hash != self.settings_hash() {
+            return Err(
+                "the checkpoint was made with another scene or other sampling settings".to_string(),
+            );
+        }
+        return Ok(());
+    }
+
+    // Render passes of `samples_per_pass` samples, from where the checkpoint stopped until
+    // pixels have `num_samples_per_pixel` samples. `after_pass` sees the checkpoint after each
+    // pass, to write previews or save it. Interrupted renders end up with the same image as
+    // renders in one go. Returns false if the render was cancelled, leaving the checkpoint
+    // partway through a pass, and an error if the checkpoint doesn't fit the camera.
+    pub fn render_passes<F: FnMut(&Checkpoint)>(
+        &mut self,
+        world: &dyn Hittable,
+        checkpoint: &mut Checkpoint,
+        mut after_pass: F,
+    ) -> Result<bool, String> {
         self.initialize();
-        // Write to a file
-        let file = File::create(image_path).unwrap();
-        let mut writer = BufWriter::new(file);
-
-        // TODO(geoff): output a better format, like png or jpg
-        // Write the header
-        writer
-            .write_fmt(format_args!(
-                "P3\n{} {}\n255\n",
-                self.image_width, self.image_height
-            ))
+        self.check_checkpoint(checkpoint)?;
+
+        let total_samples = self.num_samples_per_pixel.max(0) as u32;
+        let samples_per_pass = self.samples_per_pass.max(1) as u32;
+        let tiles = tiles(
+            self.image_width,
+            self.image_height,
+            self.tile_size,
+            self.tile_order,
+        );
+        let passes_left = total_samples
+            .saturating_sub(checkpoint.samples_per_pixel)
+            .div_ceil(samples_per_pass) as usize;
+        let progress = ProgressCounter::new(passes_left * tiles.len());
+
+        let mut stats = PathStats::default();
+        while checkpoint.samples_per_pixel < total_samples {
+            let target_samples =
+                (checkpoint.samples_per_pixel + samples_per_pass).min(total_samples);
+            let (pass_stats, pass_done) = self.render_pass(
+                world,
+                &mut checkpoint.framebuffer,
+                &tiles,
+                target_samples,
+                &progress,
+            );
+            stats = stats.merge(pass_stats);
+            if !pass_done {
+                self.path_stats = stats;
+                return Ok(false);
+            }
+            checkpoint.samples_per_pixel = target_samples;
+            after_pass(checkpoint);
+        }
+        self.path_stats = stats;
+        return Ok(true);
+    }
+
+    pub fn path_stats(&self) -> &PathStats {
+        &self.path_stats
+    }
+
+    // The image format is picked from the extension of `image_path`, defaulting to ASCII PPM
+    pub fn render(&mut self, world: &dyn Hittable, image_path: &str) -> std::io::Result<()> {
+        let format = ImageFormat::from_path(image_path).unwrap_or(ImageFormat::PpmAscii);
+        return self.render_with_format(world, image_path, format);
+    }
+
+    pub fn render_with_format(
+        &mut self,
+        world: &dyn Hittable,
+        image_path: &str,
+        format: ImageFormat,
+    ) -> std::io::Result<()> {
+        let framebuffer = self.render_to_buffer(world);
+        return framebuffer.write_image(image_path, format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::SolidBackground;
+    use crate::color::Color;
+    use crate::hittable_list::HittableList;
+    use crate::material::{
+        DiffuseLight, Lambertian, MATERIAL_BRUSHED_SILVER, MATERIAL_CONCRETE, MATERIAL_GLASS,
+        MATERIAL_SILVER,
+    };
+    use crate::progress::Progress;
+    use crate::sphere::Sphere;
+
+    const LIGHT: Color = Color::new_const(4.0, 2.0, 1.0);
+
+    fn small_camera() -> Camera {
+        Camera {
+            image_width: 8,
+            num_samples_per_pixel: 4,
+            vfov: 10.,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_emissive_sphere_fills_the_view() {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., 0., -100.),
+            90.,
+            Arc::new(DiffuseLight::new(LIGHT)),
+        )));
+
+        let framebuffer = small_camera().render_to_buffer(&world);
+        assert_eq!(framebuffer.width, 8);
+        assert_eq!(framebuffer.height, 8);
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                assert_eq!(framebuffer.sample_count(x, y), 4);
+                assert_eq!(framebuffer.pixel(x, y), LIGHT);
+            }
+        }
+    }
+
+    #[test]
+    fn test_background_color_for_missed_rays() {
+        let mut camera = small_camera();
+        camera.background = Box::new(SolidBackground::new(Color::new(0.2, 0.4, 0.6)));
+        let framebuffer = camera.render_to_buffer(&HittableList::new());
+        assert_eq!(framebuffer.pixel(5, 2), Color::new(0.2, 0.4, 0.6));
+    }
+
+    #[test]
+    fn test_lights_do_not_emit_from_their_back_face() {
+        // The camera sits inside the light
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Point3::zeros(),
+            10.,
+            Arc::new(DiffuseLight::new(LIGHT)),
+        )));
+
+        let framebuffer = small_camera().render_to_buffer(&world);
+        assert_eq!(framebuffer.pixel(3, 3), Color::black());
+    }
+
+    // Diffuse ground lit by a spherical light right above the point the camera looks at
+    fn lamp_over_ground(sample_the_light: bool) -> (Camera, HittableList) {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -1000., 0.),
+            1000.,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        let lamp = Arc::new(Sphere::new(
+            Point3::new(0., 5., 0.),
+            1.,
+            Arc::new(DiffuseLight::new(LIGHT)),
+        ));
+        world.add(Box::new(lamp.clone()));
+
+        let mut camera = Camera {
+            lookfrom: Point3::new(0., 1., 0.),
+            lookat: Point3::zeros(),
+            v_up: Vec3::new(0., 0., -1.),
+            background: Box::new(SolidBackground::new(Color::black())),
+            ..small_camera()
+        };
+        if sample_the_light {
+            camera.lights.add(Box::new(lamp));
+        }
+        return (camera, world);
+    }
+
+    #[test]
+    fn test_light_sampling_converges_quickly() {
+        // The irradiance under a sphere of radiance L is pi L (r / d)^2, a diffuse surface
+        // reflects albedo / pi of it
+        let expected = 0.5 * LIGHT / 25.;
+
+        let (mut camera, world) = lamp_over_ground(true);
+        let framebuffer = camera.render_to_buffer(&world);
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                let error = (framebuffer.pixel(x, y) - expected).length() / expected.length();
+                assert!(error < 0.05, "pixel ({}, {}) off by {}", x, y, error);
+            }
+        }
+
+        // Finding the light only by chance gives the same image, but still noisy with 250
+        // times more samples
+        let (mut camera, world) = lamp_over_ground(false);
+        camera.num_samples_per_pixel = 1000;
+        let framebuffer = camera.render_to_buffer(&world);
+        let mut mean = Color::black();
+        let mut max_error: f64 = 0.;
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                mean += framebuffer.pixel(x, y) / 64.;
+                let error = (framebuffer.pixel(x, y) - expected).length() / expected.length();
+                max_error = max_error.max(error);
+            }
+        }
+        assert!((mean - expected).length() / expected.length() < 0.05);
+        assert!(max_error > 0.05);
+    }
+
+    #[test]
+    fn test_adaptive_sampling_spends_samples_on_noise() {
+        // Looking at the horizon, the top half is sky and the bottom half is ground lit by the
+        // sky, whose color changes with the direction
+        let (camera, world) = lamp_over_ground(false);
+        let mut camera = Camera {
+            lookat: Point3::new(0., 1., -1.),
+            v_up: Vec3::new(0., 1., 0.),
+            background: Box::new(GradientBackground::default()),
+            num_samples_per_pixel: 256,
+            min_samples_per_pixel: 8,
+            noise_threshold: 0.002,
+            ..camera
+        };
+        let framebuffer = camera.render_to_buffer(&world);
+        for x in 0..framebuffer.width {
+            assert_eq!(framebuffer.sample_count(x, 0), 8);
+            let ground = framebuffer.sample_count(x, framebuffer.height - 1);
+            assert!(ground > 8 && ground < 256, "{} samples", ground);
+        }
+        assert!(framebuffer.mean_sample_count() < 200.);
+
+        // Without a threshold, every pixel gets all the samples
+        camera.noise_threshold = 0.;
+        let framebuffer = camera.render_to_buffer(&world);
+        assert_eq!(framebuffer.mean_sample_count(), 256.);
+    }
+
+    #[test]
+    fn test_identical_samples_do_not_converge() {
+        let camera = Camera {
+            min_samples_per_pixel: 4,
+            noise_threshold: 0.01,
+            ..Camera::default()
+        };
+        let variance = |values: &[f64]| {
+            let mut variance = RunningVariance::default();
+            for &value in values {
+                variance.add(value);
+            }
+            return variance;
+        };
+        assert!(!camera.converged(&variance(&[0.; 64])));
+        assert!(!camera.converged(&variance(&[0.5; 64])));
+        assert!(!camera.converged(&variance(&[0.5, 0.5001, 0.5])));
+        assert!(camera.converged(&variance(&[0.5, 0.5001, 0.5, 0.5])));
+    }
+
+    #[test]
+    fn test_resumed_renders_match_renders_in_one_go() {
+        let (camera, world) = lamp_over_ground(false);
+        let mut camera = Camera {
+            num_samples_per_pixel: 40,
+            samples_per_pass: 40,
+            min_samples_per_pixel: 4,
+            noise_threshold: 0.1,
+            seed: 99,
+            ..camera
+        };
+        let in_one_go = camera.render_to_buffer(&world);
+        // Some pixels stop early
+        assert!(in_one_go.mean_sample_count() < 40.);
+
+        // Stopped after the second pass, with the checkpoint saved to disk
+        camera.samples_per_pass = 7;
+        let mut saved = Vec::new();
+        let mut checkpoint = camera.new_checkpoint();
+        let mut passes = 0;
+        camera
+            .render_passes(&world, &mut checkpoint, |checkpoint| {
+                passes += 1;
+                if passes == 2 {
+                    checkpoint.write(&mut saved).unwrap();
+                }
+            })
             .unwrap();
+        assert_eq!(passes, 6);
+        assert!(checkpoint.framebuffer == in_one_go);
+
+        let mut resumed = Checkpoint::read(&saved[..]).unwrap();
+        assert_eq!(resumed.samples_per_pixel, 14);
+        camera.samples_per_pass = 16;
+        camera.render_passes(&world, &mut resumed, |_| ()).unwrap();
+        assert_eq!(resumed.samples_per_pixel, 40);
+        assert!(resumed.framebuffer == in_one_go);
+
+        // Another seed, image size, scene or sampling setting doesn't fit the checkpoint
+        camera.seed = 100;
+        assert!(camera.check_checkpoint(&resumed).is_err());
+        camera.seed = 99;
+        camera.noise_threshold = 0.2;
+        assert!(camera.check_checkpoint(&resumed).is_err());
+        camera.noise_threshold = 0.1;
+        camera.max_depth = 5;
+        assert!(camera.check_checkpoint(&resumed).is_err());
+        camera.max_depth = 0;
+        camera.scene_hash = 1;
+        assert!(camera.check_checkpoint(&resumed).is_err());
+        camera.scene_hash = 0;
+        camera.integrator = Box::new(crate::integrator::Normals);
+        assert!(camera.render_passes(&world, &mut resumed, |_| ()).is_err());
+        camera.integrator = Box::new(PathTracer);
+        assert!(camera.check_checkpoint(&resumed).is_ok());
+        camera.integrator = Box::new(crate::integrator::AmbientOcclusion { radius: 1. });
+        let ao_checkpoint = camera.new_checkpoint();
+        camera.integrator = Box::new(crate::integrator::AmbientOcclusion { radius: 2. });
+        assert!(camera.check_checkpoint(&ao_checkpoint).is_err());
+        camera.integrator = Box::new(PathTracer);
+        camera.image_width = 9;
+        assert!(camera.check_checkpoint(&resumed).is_err());
+    }
+
+    // Cancels the render once it has done some tiles
+    struct CancelAfter {
+        tiles: usize,
+        cancel: Arc<AtomicBool>,
+        reported: Mutex<Vec<Progress>>,
+    }
 
-        // Collect pixel colors in parallel
-        let mut flat_image = vec![0.; self.image_width * self.image_height * 3];
-        let rows: Vec<(usize, &mut [f64])> = flat_image
-            .chunks_mut(self.image_width * 3)
-            .enumerate()
-            .collect();
-
-        rows.into_par_iter()
-            .for_each(|(y, row)| self.render_line(world, y, row));
-
-        // Write pixel colors in the correct order
-        for y in 0..self.image_height {
-            let row = &flat_image[y * self.image_width * 3..(y + 1) * self.image_width * 3];
-            for x in 0..self.image_width {
-                let color = Color::from_slice(&row[3 * x..3 * (x + 1)]);
-                write_color(&mut writer, &color, self.num_samples_per_pixel).unwrap();
+    impl ProgressReporter for CancelAfter {
+        fn tile_done(&self, progress: &Progress) {
+            self.reported.lock().unwrap().push(*progress);
+            if progress.tiles_done >= self.tiles {
+                self.cancel.store(true, Ordering::Relaxed);
             }
         }
+    }
+
+    #[test]
+    fn test_tiles_and_cancelled_renders() {
+        let (camera, world) = lamp_over_ground(false);
+        let mut camera = Camera {
+            image_width: 20,
+            num_samples_per_pixel: 12,
+            samples_per_pass: 4,
+            ..camera
+        };
+        let in_one_go = camera.render_to_buffer(&world);
+
+        // Tiles of 3x3 pixels, 7x7 of them, half of them done in the second pass
+        camera.tile_size = 3;
+        camera.tile_order = TileOrder::Hilbert;
+        let reporter = Arc::new(CancelAfter {
+            tiles: 49 + 25,
+            cancel: camera.cancel.clone(),
+            reported: Mutex::new(Vec::new()),
+        });
+        camera.progress = Box::new(reporter.clone());
+        let mut checkpoint = camera.new_checkpoint();
+        let mut passes = 0;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let finished = pool
+            .install(|| camera.render_passes(&world, &mut checkpoint, |_| passes += 1))
+            .unwrap();
+        assert!(!finished);
+        assert_eq!((passes, checkpoint.samples_per_pixel), (1, 4));
+        // A single thread does one tile at a time, and stops right after the last one
+        let reported = reporter.reported.lock().unwrap().clone();
+        assert_eq!(reported.len(), 74);
+        let last = reported.last().unwrap();
+        assert_eq!((last.tiles_done, last.tiles_total), (74, 3 * 49));
+        assert!(last.rays >= 74 * 9 * 4 && last.eta().is_some());
+
+        camera.cancel.store(false, Ordering::Relaxed);
+        camera.progress = Box::new(NoProgress);
+        camera.tile_order = TileOrder::Spiral;
+        assert!(camera
+            .render_passes(&world, &mut checkpoint, |_| ())
+            .unwrap());
+        assert!(checkpoint.framebuffer == in_one_go);
+    }
+
+    #[test]
+    fn test_russian_roulette_is_unbiased_through_deep_glass() {
+        // Lossless glass under a white sky: every path ends up in the sky, so pixels are white
+        // on average, however many times the light has to go through glass
+        let mut world = HittableList::new();
+        for i in 0..20 {
+            world.add(Box::new(Sphere::new(
+                Point3::new(0., 0., -2. - 2.5 * i as f64),
+                1.,
+                MATERIAL_GLASS.clone(),
+            )));
+        }
+        let mut camera = Camera {
+            num_samples_per_pixel: 256,
+            background: Box::new(SolidBackground::new(Color::white())),
+            ..small_camera()
+        };
+        let mean_red = |framebuffer: &Framebuffer| {
+            let mut sum = 0.;
+            for y in 0..framebuffer.height {
+                for x in 0..framebuffer.width {
+                    sum += framebuffer.pixel(x, y).x;
+                }
+            }
+            sum / (framebuffer.width * framebuffer.height) as f64
+        };
+
+        let framebuffer = camera.render_to_buffer(&world);
+        let mean = mean_red(&framebuffer);
+        assert!((mean - 1.).abs() < 0.1, "mean {}", mean);
+        let stats = camera.path_stats();
+        assert_eq!(stats.paths(), 64 * 256);
+        assert!(stats.russian_roulette > 0);
+        assert_eq!(stats.max_depth, 0);
+        assert!(stats.longest > 40);
+
+        // Cutting paths short loses most of the light
+        camera.max_depth = 10;
+        camera.russian_roulette_depth = 10;
+        let framebuffer = camera.render_to_buffer(&world);
+        assert!(mean_red(&framebuffer) < 0.5);
+        let stats = camera.path_stats();
+        assert!(stats.max_depth > 0);
+        assert_eq!(stats.longest, 10);
+    }
+
+    fn render_with_threads(camera: &mut Camera, world: &dyn Hittable, threads: usize) -> Vec<u64> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let framebuffer = pool.install(|| camera.render_to_buffer(world));
+        let mut bits = Vec::new();
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                let sum = framebuffer.radiance_sum(x, y);
+                bits.extend([sum.x.to_bits(), sum.y.to_bits(), sum.z.to_bits()]);
+            }
+        }
+        return bits;
+    }
+
+    #[test]
+    fn test_seeded_renders_do_not_depend_on_threads() {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., 0., -1.),
+            0.5,
+            MATERIAL_GLASS.clone(),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(1., 0., -1.5),
+            0.5,
+            MATERIAL_SILVER.clone(),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(-1., 0., -1.5),
+            0.5,
+            MATERIAL_BRUSHED_SILVER.clone(),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -100.5, -1.),
+            100.,
+            MATERIAL_CONCRETE.clone(),
+        )));
+
+        let mut camera = Camera {
+            image_width: 16,
+            num_samples_per_pixel: 4,
+            seed: 1234,
+            ..Default::default()
+        };
+        let single_threaded = render_with_threads(&mut camera, &world, 1);
+        let multi_threaded = render_with_threads(&mut camera, &world, 4);
+        assert!(single_threaded == multi_threaded);
+
+        camera.seed = 4321;
+        let other_seed = render_with_threads(&mut camera, &world, 4);
+        assert!(single_threaded != other_seed);
+    }
+
+    #[test]
+    fn test_image_size() {
+        let mut camera = Camera {
+            image_width: 100,
+            aspect_ratio: 16. / 9.,
+            ..Default::default()
+        };
+        assert_eq!(camera.image_height(), 56);
 
-        writer.flush().unwrap();
+        // 9 / (9 / 7) is just under 7
+        camera.set_image_size(9, 7);
+        assert_eq!(camera.image_height(), 7);
+    }
+
+    #[test]
+    fn test_render_reports_write_errors() {
+        let mut camera = Camera {
+            image_width: 4,
+            num_samples_per_pixel: 1,
+            ..Default::default()
+        };
+        let result = camera.render(&HittableList::new(), "no/such/directory/image.ppm");
+        assert!(result.is_err());
     }
 }
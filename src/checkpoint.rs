@@ -0,0 +1,164 @@
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::stats::RunningVariance;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"EBCHKPT2";
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// State of an unfinished render, enough to pick it up where it stopped. Each sample gets a
+// generator seeded from the render seed, its pixel and its index, so the random state of a
+// pixel is its sample count and resumed renders end up with the same image.
+#[derive(Clone, PartialEq)]
+pub struct Checkpoint {
+    pub seed: u64,
+    // Of the scene and the settings the render was started with, see `Camera::settings_hash`
+    pub settings_hash: u64,
+    // Samples per pixel of the last completed pass, fewer for pixels that converged early
+    pub samples_per_pixel: u32,
+    pub framebuffer: Framebuffer,
+}
+
+// Little endian readers for the fields of a checkpoint
+struct Fields<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> Fields<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], std::io::Error> {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes)?;
+        return Ok(bytes);
+    }
+
+    fn u32(&mut self) -> Result<u32, std::io::Error> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, std::io::Error> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, std::io::Error> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+}
+
+impl Checkpoint {
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), std::io::Error> {
+        let mut encoder = ZlibEncoder::new(writer, Compression::fast());
+        let framebuffer = &self.framebuffer;
+        encoder.write_all(MAGIC)?;
+        encoder.write_all(&(framebuffer.width as u64).to_le_bytes())?;
+        encoder.write_all(&(framebuffer.height as u64).to_le_bytes())?;
+        encoder.write_all(&self.seed.to_le_bytes())?;
+        encoder.write_all(&self.settings_hash.to_le_bytes())?;
+        encoder.write_all(&self.samples_per_pixel.to_le_bytes())?;
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                for value in framebuffer.radiance_sum(x, y).to_array() {
+                    encoder.write_all(&value.to_le_bytes())?;
+                }
+                encoder.write_all(&framebuffer.sample_count(x, y).to_le_bytes())?;
+                let variance = framebuffer.variance(x, y);
+                encoder.write_all(&variance.count.to_le_bytes())?;
+                encoder.write_all(&variance.mean.to_le_bytes())?;
+                encoder.write_all(&variance.m2.to_le_bytes())?;
+            }
+        }
+        encoder.finish()?.flush()
+    }
+
+    pub fn read<R: Read>(reader: R) -> Result<Checkpoint, std::io::Error> {
+        let mut fields = Fields {
+            reader: ZlibDecoder::new(reader),
+        };
+        if &fields.bytes::<8>()? != MAGIC {
+            return Err(invalid_data("Not a render checkpoint"));
+        }
+        let width = fields.u64()? as usize;
+        let height = fields.u64()? as usize;
+        let seed = fields.u64()?;
+        let settings_hash = fields.u64()?;
+        let samples_per_pixel = fields.u32()?;
+
+        let n_pixels = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid_data("Checkpoint image is too large"))?;
+        let mut radiance = Vec::new();
+        let mut sample_counts = Vec::new();
+        let mut variances = Vec::new();
+        for _ in 0..n_pixels {
+            radiance.push(Color::new(fields.f64()?, fields.f64()?, fields.f64()?));
+            sample_counts.push(fields.u32()?);
+            variances.push(RunningVariance {
+                count: fields.u32()?,
+                mean: fields.f64()?,
+                m2: fields.f64()?,
+            });
+        }
+        return Ok(Checkpoint {
+            seed,
+            settings_hash,
+            samples_per_pixel,
+            framebuffer: Framebuffer::from_parts(width, height, radiance, sample_counts, variances),
+        });
+    }
+
+    // The checkpoint is written next to `path` first, so that a render killed while saving
+    // still has the previous checkpoint
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+        let path = path.as_ref();
+        let mut partial_path = path.as_os_str().to_owned();
+        partial_path.push(".partial");
+        self.write(BufWriter::new(File::create(&partial_path)?))?;
+        std::fs::rename(&partial_path, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint, std::io::Error> {
+        Checkpoint::read(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.add_sample(0, 0, Color::new(0.1, 0.2, 0.3));
+        framebuffer.add_sample(0, 0, Color::new(1e-300, 7., f64::MAX));
+        framebuffer.add_sample(2, 1, Color::new(0.5, 0.5, 0.5));
+        let checkpoint = Checkpoint {
+            seed: u64::MAX - 1,
+            settings_hash: 0x0123_4567_89ab_cdef,
+            samples_per_pixel: 2,
+            framebuffer,
+        };
+
+        let mut bytes = Vec::new();
+        checkpoint.write(&mut bytes).unwrap();
+        let read = Checkpoint::read(&bytes[..]).unwrap();
+        assert!(read == checkpoint);
+
+        // Cut short, or not a checkpoint at all
+        assert!(Checkpoint::read(&bytes[..bytes.len() / 2]).is_err());
+        assert!(Checkpoint::read(&b"P3 1 1 255 0 0 0"[..]).is_err());
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(b"P3 1 1 255 0 0 0").unwrap();
+        let error = Checkpoint::read(&encoder.finish().unwrap()[..])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -28,6 +28,11 @@ impl Color {
     pub const fn white() -> Color {
         Self::new_const(1.0, 1.0, 1.)
     }
+
+    // Perceived brightness, with the Rec. 709 weights
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
 }
 
 const INTENSITY_INTERVAL: Interval = Interval {
@@ -39,11 +44,11 @@ pub fn linear_to_gamma(x: f64) -> f64 {
     x.sqrt()
 }
 
-pub fn write_color<W: Write>(
-    mut writer: &mut BufWriter<W>,
-    pixel: &Color,
-    n_samples_per_pixel: i32,
-) -> Result<(), std::io::Error> {
+pub fn gamma_to_linear(x: f64) -> f64 {
+    x * x
+}
+
+pub fn to_rgb8(pixel: &Color, n_samples_per_pixel: i32) -> [u8; 3] {
     let scale = 1.0 / n_samples_per_pixel as f64;
 
     let r = linear_to_gamma(pixel.x() * scale);
@@ -54,6 +59,15 @@ pub fn write_color<W: Write>(
     let g = INTENSITY_INTERVAL.clamp(256.0 * g).round() as u8;
     let b = INTENSITY_INTERVAL.clamp(256.0 * b).round() as u8;
 
+    [r, g, b]
+}
+
+pub fn write_color<W: Write>(
+    mut writer: &mut BufWriter<W>,
+    pixel: &Color,
+    n_samples_per_pixel: i32,
+) -> Result<(), std::io::Error> {
+    let [r, g, b] = to_rgb8(pixel, n_samples_per_pixel);
     writeln!(&mut writer, "{} {} {}", r, g, b)
 }
 
@@ -69,4 +83,12 @@ mod tests {
         let mut writer = BufWriter::new(file);
         write_color(&mut writer, &pixel, 1).unwrap();
     }
+
+    #[test]
+    fn test_to_rgb8() {
+        assert_eq!(to_rgb8(&Color::black(), 1), [0, 0, 0]);
+        assert_eq!(to_rgb8(&Color::white(), 1), [255, 255, 255]);
+        // Gamma 2 on the average of the samples
+        assert_eq!(to_rgb8(&Color::new(0.25, 1.0, 4.0), 4), [64, 128, 255]);
+    }
 }
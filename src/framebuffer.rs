@@ -0,0 +1,280 @@
+use crate::color::{to_rgb8, Color};
+use crate::image_writer::ImageFormat;
+use crate::stats::RunningVariance;
+use crate::tiles::Tile;
+
+use std::fs::File;
+use std::ops::Range;
+
+// Linear HDR radiance accumulated per pixel, together with the number of samples taken and
+// the variance of their luminance. Pixels are stored row by row starting from the top left
+// corner.
+#[derive(Clone, PartialEq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    radiance: Vec<Color>,
+    sample_counts: Vec<u32>,
+    variances: Vec<RunningVariance>,
+}
+
+// Black through purple, red and orange to pale yellow
+const HEATMAP_COLORS: [[u8; 3]; 5] = [
+    [0, 0, 4],
+    [87, 16, 110],
+    [188, 55, 84],
+    [249, 142, 9],
+    [252, 255, 164],
+];
+
+// Color of `t` in [0, 1] on the heatmap scale, ready to be written out
+fn heatmap_color(t: f64) -> [u8; 3] {
+    let position = t.clamp(0., 1.) * (HEATMAP_COLORS.len() - 1) as f64;
+    let index = (position.floor() as usize).min(HEATMAP_COLORS.len() - 2);
+    let fraction = position - index as f64;
+    let (low, high) = (HEATMAP_COLORS[index], HEATMAP_COLORS[index + 1]);
+    let mut color = [0; 3];
+    for channel in 0..3 {
+        let (low, high) = (low[channel] as f64, high[channel] as f64);
+        color[channel] = (low + fraction * (high - low)).round() as u8;
+    }
+    return color;
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            radiance: vec![Color::black(); width * height],
+            sample_counts: vec![0; width * height],
+            variances: vec![RunningVariance::default(); width * height],
+        }
+    }
+
+    // Panics if the pixel vectors don't all have `width * height` pixels
+    pub fn from_parts(
+        width: usize,
+        height: usize,
+        radiance: Vec<Color>,
+        sample_counts: Vec<u32>,
+        variances: Vec<RunningVariance>,
+    ) -> Self {
+        let n_pixels = width * height;
+        assert!(
+            radiance.len() == n_pixels
+                && sample_counts.len() == n_pixels
+                && variances.len() == n_pixels,
+            "Framebuffer parts must have one value per pixel"
+        );
+        Self {
+            width,
+            height,
+            radiance,
+            sample_counts,
+            variances,
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        assert!(x < self.width && y < self.height, "Pixel out of bounds");
+        y * self.width + x
+    }
+
+    pub fn add_sample(&mut self, x: usize, y: usize, color: Color) {
+        let index = self.index(x, y);
+        self.radiance[index] += color;
+        self.sample_counts[index] += 1;
+        self.variances[index].add(color.luminance());
+    }
+
+    // Sum of all the samples taken for this pixel
+    pub fn radiance_sum(&self, x: usize, y: usize) -> Color {
+        self.radiance[self.index(x, y)]
+    }
+
+    pub fn sample_count(&self, x: usize, y: usize) -> u32 {
+        self.sample_counts[self.index(x, y)]
+    }
+
+    // Spread of the luminance of the samples taken for this pixel
+    pub fn variance(&self, x: usize, y: usize) -> &RunningVariance {
+        &self.variances[self.index(x, y)]
+    }
+
+    pub fn mean_sample_count(&self) -> f64 {
+        let total: u64 = self.sample_counts.iter().map(|&count| count as u64).sum();
+        return total as f64 / self.sample_counts.len().max(1) as f64;
+    }
+
+    // Mean radiance of the pixel, black if it has not been sampled
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let index = self.index(x, y);
+        let count = self.sample_counts[index];
+        if count == 0 {
+            return Color::black();
+        }
+        return self.radiance[index] / count as f64;
+    }
+
+    // Ranges of each row of a tile, in the pixels of the framebuffer and of the tile
+    fn tile_rows(&self, tile: &Tile) -> Vec<(Range<usize>, Range<usize>)> {
+        assert!(
+            tile.x + tile.width <= self.width && tile.y + tile.height <= self.height,
+            "Tile out of bounds"
+        );
+        (0..tile.height)
+            .map(|y| {
+                let start = (tile.y + y) * self.width + tile.x;
+                let tile_start = y * tile.width;
+                (
+                    start..start + tile.width,
+                    tile_start..tile_start + tile.width,
+                )
+            })
+            .collect()
+    }
+
+    // Copy of the pixels of a tile, as a framebuffer of the size of the tile
+    pub fn tile(&self, tile: &Tile) -> Framebuffer {
+        let mut pixels = Framebuffer::new(tile.width, tile.height);
+        for (range, tile_range) in self.tile_rows(tile) {
+            pixels.radiance[tile_range.clone()].copy_from_slice(&self.radiance[range.clone()]);
+            pixels.sample_counts[tile_range.clone()]
+                .copy_from_slice(&self.sample_counts[range.clone()]);
+            pixels.variances[tile_range].copy_from_slice(&self.variances[range]);
+        }
+        return pixels;
+    }
+
+    // Put back the pixels of a tile taken out with `tile`
+    pub fn set_tile(&mut self, tile: &Tile, pixels: &Framebuffer) {
+        assert!(
+            (pixels.width, pixels.height) == (tile.width, tile.height),
+            "Tile pixels must have the size of the tile"
+        );
+        for (range, tile_range) in self.tile_rows(tile) {
+            self.radiance[range.clone()].copy_from_slice(&pixels.radiance[tile_range.clone()]);
+            self.sample_counts[range.clone()]
+                .copy_from_slice(&pixels.sample_counts[tile_range.clone()]);
+            self.variances[range].copy_from_slice(&pixels.variances[tile_range]);
+        }
+    }
+
+    // Gamma corrected 8-bit pixels, ready to be written out
+    pub fn to_rgb8(&self) -> Vec<[u8; 3]> {
+        self.radiance
+            .iter()
+            .zip(&self.sample_counts)
+            .map(|(sum, &count)| {
+                if count == 0 {
+                    [0, 0, 0]
+                } else {
+                    to_rgb8(sum, count as i32)
+                }
+            })
+            .collect()
+    }
+
+    pub fn write_image(&self, image_path: &str, format: ImageFormat) -> Result<(), std::io::Error> {
+        let mut file = File::create(image_path)?;
+        format
+            .writer()
+            .write(&mut file, self.width, self.height, &self.to_rgb8())
+    }
+
+    // Number of samples taken for each pixel, from dark for the fewest to bright for the most
+    // samples of the image
+    pub fn sample_count_heatmap(&self) -> Vec<[u8; 3]> {
+        let max_count = self.sample_counts.iter().copied().max().unwrap_or(0).max(1);
+        self.sample_counts
+            .iter()
+            .map(|&count| heatmap_color(count as f64 / max_count as f64))
+            .collect()
+    }
+
+    pub fn write_heatmap(
+        &self,
+        image_path: &str,
+        format: ImageFormat,
+    ) -> Result<(), std::io::Error> {
+        let mut file = File::create(image_path)?;
+        format.writer().write(
+            &mut file,
+            self.width,
+            self.height,
+            &self.sample_count_heatmap(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_is_mean_of_samples() {
+        let mut framebuffer = Framebuffer::new(2, 1);
+        framebuffer.add_sample(1, 0, Color::new(1.0, 0.0, 0.5));
+        framebuffer.add_sample(1, 0, Color::new(0.0, 0.0, 0.5));
+        assert_eq!(framebuffer.sample_count(0, 0), 0);
+        assert_eq!(framebuffer.sample_count(1, 0), 2);
+        assert_eq!(framebuffer.radiance_sum(1, 0), Color::new(1.0, 0.0, 1.0));
+        assert_eq!(framebuffer.pixel(0, 0), Color::black());
+        assert_eq!(framebuffer.pixel(1, 0), Color::new(0.5, 0.0, 0.5));
+    }
+
+    #[test]
+    fn test_to_rgb8() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                framebuffer.add_sample(x, y, Color::new(x as f64, y as f64, 0.0));
+            }
+        }
+        assert_eq!(framebuffer.pixel(2, 1), Color::new(2.0, 1.0, 0.0));
+        assert_eq!(framebuffer.to_rgb8()[0], [0, 0, 0]);
+        assert_eq!(framebuffer.to_rgb8()[5], [255, 255, 0]);
+    }
+
+    #[test]
+    fn test_sample_count_heatmap() {
+        let mut framebuffer = Framebuffer::new(3, 1);
+        for _ in 0..4 {
+            framebuffer.add_sample(2, 0, Color::white());
+        }
+        framebuffer.add_sample(1, 0, Color::new(1.0, 1.0, 1.0));
+        framebuffer.add_sample(1, 0, Color::new(3.0, 3.0, 3.0));
+        assert_eq!(framebuffer.mean_sample_count(), 2.0);
+        assert_eq!(framebuffer.variance(1, 0).variance(), 2.0);
+
+        let heatmap = framebuffer.sample_count_heatmap();
+        assert_eq!(
+            heatmap,
+            vec![HEATMAP_COLORS[0], HEATMAP_COLORS[2], HEATMAP_COLORS[4]]
+        );
+        assert_eq!(heatmap_color(0.125), [44, 8, 57]);
+    }
+
+    #[test]
+    fn test_tile_roundtrip() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        framebuffer.add_sample(2, 1, Color::new(1.0, 2.0, 3.0));
+        let tile = Tile {
+            x: 1,
+            y: 1,
+            width: 3,
+            height: 2,
+        };
+        let mut pixels = framebuffer.tile(&tile);
+        assert_eq!(pixels.pixel(1, 0), Color::new(1.0, 2.0, 3.0));
+        pixels.add_sample(2, 1, Color::white());
+        pixels.add_sample(1, 0, Color::white());
+
+        framebuffer.set_tile(&tile, &pixels);
+        assert_eq!(framebuffer.sample_count(3, 2), 1);
+        assert_eq!(framebuffer.radiance_sum(2, 1), Color::new(2.0, 3.0, 4.0));
+        assert_eq!(framebuffer.variance(2, 1).count, 2);
+        assert_eq!(framebuffer.mean_sample_count(), 3.0 / 12.0);
+    }
+}
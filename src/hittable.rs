@@ -1,20 +1,26 @@
-use crate::color::Color;
+use crate::aabb::Aabb;
 use crate::interval::Interval;
 use crate::material::{Material, MATERIAL_CONCRETE};
 use crate::ray::Ray;
+use crate::rng::SampleRng;
 use crate::vec::{Point3, Vec3};
 
+use std::sync::Arc;
+
 #[derive(Clone, Copy)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub p: Point3,
     pub normal: Vec3,
     pub t: f64,
-    pub color: Color,
+    // Surface coordinates of the hit point
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
-    pub material: &'static dyn Material,
+    // Borrowed from the object that was hit
+    pub material: &'a dyn Material,
 }
 
-impl HitRecord {
+impl HitRecord<'_> {
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
         // outward normal is supposed to be a unit vector
         self.front_face = r.direction.dot(&outward_normal) < 0.0;
@@ -26,19 +32,79 @@ impl HitRecord {
     }
 }
 
-impl Default for HitRecord {
+impl Default for HitRecord<'_> {
     fn default() -> Self {
         HitRecord {
             p: Point3::default(),
             normal: Vec3::default(),
             t: 0.0,
-            color: Color::default(),
+            u: 0.0,
+            v: 0.0,
             front_face: true,
-            material: &MATERIAL_CONCRETE,
+            material: &**MATERIAL_CONCRETE,
         }
     }
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>>;
+    fn bounding_box(&self) -> Aabb;
+}
+
+// Objects that can be sampled directly, the only ones the camera takes as lights
+pub trait Light: Hittable {
+    // Density, over solid angles seen from `origin`, of `random` picking `direction`
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64;
+
+    // Direction from `origin` towards a random point of the object
+    fn random(&self, rng: &mut SampleRng, origin: &Point3) -> Vec3;
+}
+
+// Lets shared objects be added to lists and trees like owned ones
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        (**self).hit(ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+}
+
+impl<T: Light + ?Sized> Light for Arc<T> {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random(&self, rng: &mut SampleRng, origin: &Point3) -> Vec3 {
+        (**self).random(rng, origin)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::f64::consts::PI;
+
+    // Checks that the directions `object` picks from `origin` point at it, and that their
+    // density integrates to one over the sphere of directions, within `tolerance`
+    pub fn check_light_sampling_pdf<L: Light>(
+        object: &L,
+        origin: &Point3,
+        n_directions: usize,
+        tolerance: f64,
+    ) {
+        let mut rng = SampleRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let direction = object.random(&mut rng, origin);
+            assert!(object.pdf_value(origin, &direction) > 0.);
+        }
+
+        let total: f64 = (0..n_directions)
+            .map(|_| object.pdf_value(origin, &Vec3::random_unit_vector(&mut rng)))
+            .sum();
+        let integral = 4. * PI * total / n_directions as f64;
+        assert!((integral - 1.).abs() < tolerance, "integral {}", integral);
+    }
 }
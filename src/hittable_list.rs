@@ -1,13 +1,23 @@
-use crate::hittable::{HitRecord, Hittable};
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, Light};
 use crate::interval::Interval;
 use crate::ray::Ray;
+use crate::rng::SampleRng;
+use crate::vec::{Point3, Vec3};
 
+use rand::Rng;
 use std::vec::Vec;
 
 pub struct HittableList {
     pub objects: Vec<Box<dyn Hittable>>,
 }
 
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HittableList {
     pub fn new() -> Self {
         Self {
@@ -21,17 +31,124 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let mut closest_so_far = ray_t.upper;
-
-        let mut record = None;
-        for object in &self.objects {
-            // A hit updates temp_record
-            if let Some(temp_record) = object.hit(ray, Interval::new(ray_t.lower, closest_so_far)) {
-                closest_so_far = temp_record.t;
-                record = Some(temp_record);
-            }
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        return closest_hit(&self.objects, ray, ray_t);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return enclosing_box(&self.objects);
+    }
+}
+
+// The lights of a scene, picked uniformly whatever their size when sampling
+pub struct LightList {
+    pub objects: Vec<Box<dyn Light>>,
+}
+
+impl Default for LightList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
         }
-        return record;
+    }
+
+    pub fn add(&mut self, light: Box<dyn Light>) {
+        self.objects.push(light);
+    }
+}
+
+impl Hittable for LightList {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        return closest_hit(&self.objects, ray, ray_t);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return enclosing_box(&self.objects);
+    }
+}
+
+impl Light for LightList {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        return sum / self.objects.len() as f64;
+    }
+
+    // Without lights there is no direction to pick, the zero vector has a pdf of zero
+    fn random(&self, rng: &mut SampleRng, origin: &Point3) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::zeros();
+        }
+        let index = rng.gen_range(0..self.objects.len());
+        return self.objects[index].random(rng, origin);
+    }
+}
+
+fn closest_hit<'a, T: Hittable + ?Sized>(
+    objects: &'a [Box<T>],
+    ray: &Ray,
+    ray_t: Interval,
+) -> Option<HitRecord<'a>> {
+    let mut closest_so_far = ray_t.upper;
+
+    let mut record = None;
+    for object in objects {
+        // A hit updates temp_record
+        if let Some(temp_record) = object.hit(ray, Interval::new(ray_t.lower, closest_so_far)) {
+            closest_so_far = temp_record.t;
+            record = Some(temp_record);
+        }
+    }
+    return record;
+}
+
+fn enclosing_box<T: Hittable + ?Sized>(objects: &[Box<T>]) -> Aabb {
+    objects.iter().fold(Aabb::empty(), |bbox, object| {
+        Aabb::enclosing(&bbox, &object.bounding_box())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::{Lambertian, Material};
+    use crate::sphere::Sphere;
+    use crate::vec::Point3;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_dropping_world_frees_materials() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let weak = Arc::downgrade(&material);
+
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::zeros(), 1., material.clone())));
+        world.add(Box::new(Sphere::new(Point3::new(2., 0., 0.), 1., material)));
+        assert_eq!(weak.strong_count(), 2);
+
+        drop(world);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_empty_light_list() {
+        let lights = LightList::new();
+        let mut rng = SampleRng::seed_from_u64(0);
+        let direction = lights.random(&mut rng, &Point3::zeros());
+        assert_eq!(lights.pdf_value(&Point3::zeros(), &direction), 0.);
     }
 }
@@ -0,0 +1,360 @@
+use flate2::read::ZlibDecoder;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Product of sizes read from a file header, which can be anything
+fn checked_size(factors: &[usize]) -> Result<usize, std::io::Error> {
+    factors
+        .iter()
+        .try_fold(1usize, |product, &factor| product.checked_mul(factor))
+        .ok_or_else(|| invalid_data("Image is too large"))
+}
+
+// Reads a PNG or PPM (ASCII or binary) image as 8-bit RGB pixels, row by row starting from
+// the top left corner. The format is guessed from the content, not the extension.
+pub fn read_image<P: AsRef<Path>>(path: P) -> Result<(usize, usize, Vec<[u8; 3]>), std::io::Error> {
+    let data = std::fs::read(path)?;
+    if data.starts_with(&PNG_SIGNATURE) {
+        return read_png(&data);
+    }
+    if data.starts_with(b"P3") || data.starts_with(b"P6") {
+        return read_ppm(&data);
+    }
+    return Err(invalid_data(
+        "Unsupported image format, expected PNG or PPM",
+    ));
+}
+
+// Whitespace separated header fields of a PPM file, skipping comments
+struct PpmHeader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl PpmHeader<'_> {
+    fn token(&mut self) -> Result<&[u8], std::io::Error> {
+        loop {
+            match self.data.get(self.offset) {
+                Some(b'#') => {
+                    while self.data.get(self.offset).is_some_and(|&c| c != b'\n') {
+                        self.offset += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.offset += 1,
+                Some(_) => break,
+                None => return Err(invalid_data("Unexpected end of PPM file")),
+            }
+        }
+        let start = self.offset;
+        while self
+            .data
+            .get(self.offset)
+            .is_some_and(|c| !c.is_ascii_whitespace())
+        {
+            self.offset += 1;
+        }
+        return Ok(&self.data[start..self.offset]);
+    }
+
+    fn number(&mut self) -> Result<usize, std::io::Error> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| invalid_data("Invalid number in PPM file"))
+    }
+}
+
+pub fn read_ppm(data: &[u8]) -> Result<(usize, usize, Vec<[u8; 3]>), std::io::Error> {
+    let mut header = PpmHeader { data, offset: 0 };
+    let binary = match header.token()? {
+        b"P3" => false,
+        b"P6" => true,
+        _ => return Err(invalid_data("Not a PPM file")),
+    };
+    let width = header.number()?;
+    let height = header.number()?;
+    let max_value = header.number()?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data("Invalid PPM maximum value"));
+    }
+    let n_values = checked_size(&[3, width, height])?;
+    let to_u8 = |value: usize| (value.min(max_value) * 255 / max_value) as u8;
+
+    let mut values = Vec::new();
+    if binary {
+        // A single whitespace separates the header from the raster
+        let start = header.offset + 1;
+        let bytes_per_value = if max_value < 256 { 1 } else { 2 };
+        let raster = checked_size(&[bytes_per_value, n_values])
+            .ok()
+            .and_then(|length| data.get(start..start.checked_add(length)?))
+            .ok_or_else(|| invalid_data("Truncated PPM pixel data"))?;
+        values.reserve(n_values);
+        for value in raster.chunks(bytes_per_value) {
+            let value = value
+                .iter()
+                .fold(0, |acc, &byte| (acc << 8) | byte as usize);
+            values.push(to_u8(value));
+        }
+    } else {
+        // Values take two bytes at least, with their separator
+        if n_values > data.len() / 2 {
+            return Err(invalid_data("Truncated PPM pixel data"));
+        }
+        values.reserve(n_values);
+        for _ in 0..n_values {
+            values.push(to_u8(header.number()?));
+        }
+    }
+    let pixels = values
+        .chunks(3)
+        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+        .collect();
+    return Ok((width, height, pixels));
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let p = left as i16 + up as i16 - up_left as i16;
+    let distance_left = (p - left as i16).abs();
+    let distance_up = (p - up as i16).abs();
+    let distance_up_left = (p - up_left as i16).abs();
+    if distance_left <= distance_up && distance_left <= distance_up_left {
+        return left;
+    }
+    if distance_up <= distance_up_left {
+        return up;
+    }
+    return up_left;
+}
+
+// Undo the per-scanline filters in place. `bpp` is the number of bytes per complete pixel.
+fn unfilter(
+    raw: &mut [u8],
+    height: usize,
+    stride: usize,
+    bpp: usize,
+) -> Result<(), std::io::Error> {
+    for y in 0..height {
+        let (previous, current) = raw.split_at_mut(y * (stride + 1));
+        let previous = if y == 0 {
+            None
+        } else {
+            Some(&previous[previous.len() - stride..])
+        };
+        let filter = current[0];
+        let row = &mut current[1..=stride];
+        for i in 0..stride {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let up = previous.map_or(0, |previous| previous[i]);
+            let up_left = match previous {
+                Some(previous) if i >= bpp => previous[i - bpp],
+                _ => 0,
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(invalid_data("Invalid PNG filter type")),
+            };
+            row[i] = row[i].wrapping_add(predictor);
+        }
+    }
+    return Ok(());
+}
+
+// Only non-interlaced images with 8 or 16 bits per channel are supported. Alpha is ignored.
+pub fn read_png(data: &[u8]) -> Result<(usize, usize, Vec<[u8; 3]>), std::io::Error> {
+    if !data.starts_with(&PNG_SIGNATURE) {
+        return Err(invalid_data("Not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    loop {
+        let chunk_header = data
+            .get(offset..offset + 8)
+            .ok_or_else(|| invalid_data("Truncated PNG file"))?;
+        let length = u32::from_be_bytes(chunk_header[..4].try_into().unwrap()) as usize;
+        let chunk_type = &chunk_header[4..];
+        let chunk = data
+            .get(offset + 4..offset + 12 + length)
+            .ok_or_else(|| invalid_data("Truncated PNG chunk"))?;
+        let (typed_data, crc) = chunk.split_at(4 + length);
+        if crc32fast::hash(typed_data).to_be_bytes() != crc {
+            return Err(invalid_data("Corrupted PNG chunk"));
+        }
+        let chunk_data = &typed_data[4..];
+        offset += 12 + length;
+
+        match chunk_type {
+            b"IHDR" => {
+                if length != 13 {
+                    return Err(invalid_data("Invalid PNG header"));
+                }
+                header = Some(chunk_data.to_vec());
+            }
+            b"PLTE" => palette = chunk_data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(chunk_data),
+            b"IEND" => break,
+            // Ancillary chunks, like gamma or text, are skipped
+            _ => (),
+        }
+    }
+
+    let header = header.ok_or_else(|| invalid_data("Missing PNG header"))?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let (bit_depth, color_type, interlace) = (header[8], header[9], header[12]);
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid_data("Invalid PNG color type")),
+    };
+    if !(bit_depth == 8 || (bit_depth == 16 && color_type != 3)) {
+        return Err(invalid_data("Unsupported PNG bit depth"));
+    }
+    if interlace != 0 {
+        return Err(invalid_data("Interlaced PNG images are not supported"));
+    }
+
+    let bytes_per_sample = bit_depth as usize / 8;
+    let bpp = channels * bytes_per_sample;
+    let stride = checked_size(&[width, bpp])?;
+    let raw_length = checked_size(&[height, stride + 1])?;
+    let mut raw = Vec::new();
+    ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut raw)?;
+    if raw.len() < raw_length {
+        return Err(invalid_data("Truncated PNG pixel data"));
+    }
+    unfilter(&mut raw, height, stride, bpp)?;
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for pixel in row.chunks(bpp) {
+            // Keep the most significant byte of 16-bit samples
+            let sample = |channel: usize| pixel[channel * bytes_per_sample];
+            let rgb = match color_type {
+                0 | 4 => [sample(0); 3],
+                3 => {
+                    let index = 3 * sample(0) as usize;
+                    let entry = palette
+                        .get(index..index + 3)
+                        .ok_or_else(|| invalid_data("PNG palette index out of range"))?;
+                    [entry[0], entry[1], entry[2]]
+                }
+                _ => [sample(0), sample(1), sample(2)],
+            };
+            pixels.push(rgb);
+        }
+    }
+    return Ok((width, height, pixels));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_writer::{ImageWriter, PngWriter, PpmAsciiWriter, PpmBinaryWriter};
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const PIXELS: [[u8; 3]; 6] = [
+        [255, 0, 0],
+        [0, 255, 0],
+        [0, 0, 255],
+        [0, 0, 0],
+        [128, 128, 128],
+        [255, 255, 255],
+    ];
+
+    #[test]
+    fn test_ppm_roundtrip() {
+        let writers: [&dyn ImageWriter; 2] = [&PpmAsciiWriter, &PpmBinaryWriter];
+        for writer in writers {
+            let mut output = Vec::new();
+            writer.write(&mut output, 3, 2, &PIXELS).unwrap();
+            let (width, height, pixels) = read_ppm(&output).unwrap();
+            assert_eq!((width, height), (3, 2));
+            assert_eq!(pixels, PIXELS);
+        }
+
+        let (_, _, pixels) = read_ppm(b"P3\n# a comment\n1 1\n15\n15 0 5\n").unwrap();
+        assert_eq!(pixels, vec![[255, 0, 85]]);
+
+        // Huge sizes in the header are errors, not overflows or huge allocations
+        for header in [
+            "P6 100000 100000 255\n\0\0\0",
+            "P3 100000 100000 255\n0 0 0",
+            "P6 18446744073709551615 2 255\n\0\0\0",
+        ] {
+            let error = read_ppm(header.as_bytes()).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_png_roundtrip() {
+        let mut output = Vec::new();
+        PngWriter.write(&mut output, 3, 2, &PIXELS).unwrap();
+        let (width, height, pixels) = read_png(&output).unwrap();
+        assert_eq!((width, height), (3, 2));
+        assert_eq!(pixels, PIXELS);
+    }
+
+    fn png(header: [u8; 13], palette: Option<&[u8]>, raw: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut output = PNG_SIGNATURE.to_vec();
+        let mut chunks = vec![(b"IHDR", header.to_vec())];
+        if let Some(palette) = palette {
+            chunks.push((b"PLTE", palette.to_vec()));
+        }
+        chunks.push((b"IDAT", compressed));
+        chunks.push((b"IEND", Vec::new()));
+        for (chunk_type, data) in chunks {
+            output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            let mut typed_data = chunk_type.to_vec();
+            typed_data.extend_from_slice(&data);
+            output.extend_from_slice(&typed_data);
+            output.extend_from_slice(&crc32fast::hash(&typed_data).to_be_bytes());
+        }
+        return output;
+    }
+
+    #[test]
+    fn test_png_filters_and_color_types() {
+        // 2x2 grayscale with alpha, one row using the Sub filter and one the Paeth filter
+        let header = [0, 0, 0, 2, 0, 0, 0, 2, 8, 4, 0, 0, 0];
+        let raw = [1, 10, 255, 20, 0, 4, 30, 255, 5, 0];
+        let (_, _, pixels) = read_png(&png(header, None, &raw)).unwrap();
+        assert_eq!(pixels, vec![[10; 3], [30; 3], [40; 3], [45; 3]]);
+
+        // 2x1 palette image using the Up filter on its only row
+        let header = [0, 0, 0, 2, 0, 0, 0, 1, 8, 3, 0, 0, 0];
+        let palette = [1, 2, 3, 4, 5, 6];
+        let (_, _, pixels) = read_png(&png(header, Some(&palette), &[2, 1, 0])).unwrap();
+        assert_eq!(pixels, vec![[4, 5, 6], [1, 2, 3]]);
+
+        // Corrupting a byte is caught by the checksums
+        let mut corrupted = png(header, Some(&palette), &[2, 1, 0]);
+        corrupted[20] ^= 1;
+        assert!(read_png(&corrupted).is_err());
+    }
+}
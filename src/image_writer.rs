@@ -0,0 +1,217 @@
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    PpmAscii,
+    PpmBinary,
+    Png,
+}
+
+impl ImageFormat {
+    // Guess the format from the file extension. `.ppm` keeps the historical ASCII output,
+    // binary PPM has to be asked for explicitly.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::PpmAscii),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    pub fn writer(&self) -> Box<dyn ImageWriter> {
+        match self {
+            ImageFormat::PpmAscii => Box::new(PpmAsciiWriter),
+            ImageFormat::PpmBinary => Box::new(PpmBinaryWriter),
+            ImageFormat::Png => Box::new(PngWriter),
+        }
+    }
+}
+
+// Writes 8-bit RGB pixels, row by row starting from the top left corner.
+pub trait ImageWriter {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        width: usize,
+        height: usize,
+        pixels: &[[u8; 3]],
+    ) -> Result<(), std::io::Error>;
+}
+
+pub struct PpmAsciiWriter;
+
+impl ImageWriter for PpmAsciiWriter {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        width: usize,
+        height: usize,
+        pixels: &[[u8; 3]],
+    ) -> Result<(), std::io::Error> {
+        let mut writer = BufWriter::new(writer);
+        write!(writer, "P3\n{} {}\n255\n", width, height)?;
+        for [r, g, b] in pixels {
+            writeln!(writer, "{} {} {}", r, g, b)?;
+        }
+        writer.flush()
+    }
+}
+
+pub struct PpmBinaryWriter;
+
+impl ImageWriter for PpmBinaryWriter {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        width: usize,
+        height: usize,
+        pixels: &[[u8; 3]],
+    ) -> Result<(), std::io::Error> {
+        let mut writer = BufWriter::new(writer);
+        write!(writer, "P6\n{} {}\n255\n", width, height)?;
+        writer.write_all(pixels.as_flattened())?;
+        writer.flush()
+    }
+}
+
+pub struct PngWriter;
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+impl PngWriter {
+    fn write_chunk(
+        writer: &mut dyn Write,
+        chunk_type: &[u8; 4],
+        data: &[u8],
+    ) -> Result<(), std::io::Error> {
+        writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        writer.write_all(chunk_type)?;
+        writer.write_all(data)?;
+
+        // The CRC covers the chunk type and data, not the length
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(chunk_type);
+        hasher.update(data);
+        writer.write_all(&hasher.finalize().to_be_bytes())
+    }
+}
+
+impl ImageWriter for PngWriter {
+    fn write(
+        &self,
+        writer: &mut dyn Write,
+        width: usize,
+        height: usize,
+        pixels: &[[u8; 3]],
+    ) -> Result<(), std::io::Error> {
+        writer.write_all(&PNG_SIGNATURE)?;
+
+        // 8 bits per channel, truecolor, deflate, adaptive filtering, no interlacing
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        Self::write_chunk(writer, b"IHDR", &header)?;
+
+        // Every scanline starts with its filter type, we don't filter (type 0)
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for row in pixels.chunks(width.max(1)) {
+            encoder.write_all(&[0])?;
+            encoder.write_all(row.as_flattened())?;
+        }
+        let compressed = encoder.finish()?;
+        Self::write_chunk(writer, b"IDAT", &compressed)?;
+
+        Self::write_chunk(writer, b"IEND", &[])?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    const PIXELS: [[u8; 3]; 6] = [
+        [255, 0, 0],
+        [0, 255, 0],
+        [0, 0, 255],
+        [0, 0, 0],
+        [128, 128, 128],
+        [255, 255, 255],
+    ];
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ImageFormat::from_path("render.ppm"),
+            Some(ImageFormat::PpmAscii)
+        );
+        assert_eq!(ImageFormat::from_path("render.PNG"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path("render.jpg"), None);
+        assert_eq!(ImageFormat::from_path("render"), None);
+    }
+
+    #[test]
+    fn test_ppm_ascii() {
+        let mut output = Vec::new();
+        PpmAsciiWriter.write(&mut output, 3, 2, &PIXELS).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.starts_with("P3\n3 2\n255\n255 0 0\n0 255 0\n"));
+        assert_eq!(text.lines().count(), 3 + 6);
+    }
+
+    #[test]
+    fn test_ppm_binary() {
+        let mut output = Vec::new();
+        PpmBinaryWriter.write(&mut output, 3, 2, &PIXELS).unwrap();
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&output[..header.len()], header);
+        assert_eq!(&output[header.len()..], PIXELS.as_flattened());
+    }
+
+    #[test]
+    fn test_png_roundtrip() {
+        let mut output = Vec::new();
+        PngWriter.write(&mut output, 3, 2, &PIXELS).unwrap();
+        assert_eq!(output[..8], PNG_SIGNATURE);
+
+        // Walk the chunks and check their CRCs
+        let mut offset = 8;
+        let mut chunks = Vec::new();
+        while offset < output.len() {
+            let length = u32::from_be_bytes(output[offset..offset + 4].try_into().unwrap());
+            let length = length as usize;
+            let chunk_type = &output[offset + 4..offset + 8];
+            let data = &output[offset + 8..offset + 8 + length];
+            let crc = u32::from_be_bytes(
+                output[offset + 8 + length..offset + 12 + length]
+                    .try_into()
+                    .unwrap(),
+            );
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(chunk_type);
+            hasher.update(data);
+            assert_eq!(hasher.finalize(), crc);
+            chunks.push((chunk_type.to_vec(), data.to_vec()));
+            offset += 12 + length;
+        }
+        let types: Vec<&[u8]> = chunks.iter().map(|(t, _)| t.as_slice()).collect();
+        assert_eq!(types, vec![b"IHDR", b"IDAT", b"IEND"]);
+
+        let mut raw = Vec::new();
+        ZlibDecoder::new(chunks[1].1.as_slice())
+            .read_to_end(&mut raw)
+            .unwrap();
+        assert_eq!(raw.len(), 2 * (1 + 3 * 3));
+        assert_eq!(raw[0], 0);
+        assert_eq!(&raw[1..10], PIXELS[..3].as_flattened());
+        assert_eq!(raw[10], 0);
+        assert_eq!(&raw[11..], PIXELS[3..].as_flattened());
+    }
+}
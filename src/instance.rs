@@ -0,0 +1,152 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::matrix::Matrix4;
+use crate::ray::Ray;
+use crate::vec::Point3;
+
+use std::sync::Arc;
+
+// Shared object placed in the world by an affine transform. The same object, e.g. a big
+// mesh, can be instanced many times without being duplicated.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    // Object to world space, and back
+    transform: Matrix4,
+    inverse: Matrix4,
+    // Transposed inverse, which maps object normals to world normals
+    normal_transform: Matrix4,
+    bbox: Aabb,
+}
+
+impl Instance {
+    // Fails if the transform can't be inverted, e.g. for a zero scale
+    pub fn new(object: Arc<dyn Hittable>, transform: Matrix4) -> Result<Self, String> {
+        let inverse = transform
+            .inverse()
+            .ok_or_else(|| "instance transforms must be invertible".to_string())?;
+        let bbox = Self::transformed_box(&object.bounding_box(), &transform);
+        Ok(Instance {
+            object,
+            transform,
+            inverse,
+            normal_transform: inverse.transpose(),
+            bbox,
+        })
+    }
+
+    // Box around the transformed corners of the object box
+    fn transformed_box(bbox: &Aabb, transform: &Matrix4) -> Aabb {
+        if bbox.is_empty() {
+            return Aabb::empty();
+        }
+        // Infinite corners don't transform well, and whatever is unbounded stays so
+        if bbox.is_unbounded() {
+            return Aabb::universe();
+        }
+        let mut transformed = Aabb::empty();
+        for x in [bbox.x.lower, bbox.x.upper] {
+            for y in [bbox.y.lower, bbox.y.upper] {
+                for z in [bbox.z.lower, bbox.z.upper] {
+                    let corner = transform.transform_point(&Point3::new(x, y, z));
+                    transformed = Aabb::enclosing(&transformed, &Aabb::from_points(corner, corner));
+                }
+            }
+        }
+        return transformed;
+    }
+
+    pub fn transform(&self) -> &Matrix4 {
+        &self.transform
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        // The direction is not normalized, so the ray parameter is the same in both spaces
+        let object_ray = Ray::with_time(
+            self.inverse.transform_point(&ray.origin),
+            self.inverse.transform_vector(&ray.direction),
+            ray.time,
+        );
+        let mut record = self.object.hit(&object_ray, ray_t)?;
+
+        // Affine transforms keep the side of the surface the ray comes from, so `front_face`
+        // still holds
+        record.p = self.transform.transform_point(&record.p);
+        record.normal = self
+            .normal_transform
+            .transform_vector(&record.normal)
+            .normalize();
+        return Some(record);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MATERIAL_CONCRETE;
+    use crate::sphere::Sphere;
+    use crate::triangle::Triangle;
+    use crate::vec::Vec3;
+
+    #[test]
+    fn test_instanced_sphere() {
+        let sphere: Arc<dyn Hittable> =
+            Arc::new(Sphere::new(Point3::zeros(), 1., MATERIAL_CONCRETE.clone()));
+        // Stretched along x and moved away from the origin
+        let transform =
+            Matrix4::translation(Vec3::new(10., 0., 0.)) * Matrix4::scaling(Vec3::new(2., 1., 1.));
+        let instance = Instance::new(sphere.clone(), transform).unwrap();
+
+        let bbox = instance.bounding_box();
+        assert_eq!((bbox.x.lower, bbox.x.upper), (8., 12.));
+        assert_eq!((bbox.y.lower, bbox.y.upper), (-1., 1.));
+
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let ray = Ray::new(Point3::new(0., 0., 0.), Vec3::new(1., 0., 0.));
+        let record = instance.hit(&ray, ray_t).unwrap();
+        assert_eq!(record.t, 8.);
+        assert_eq!(record.p, Point3::new(8., 0., 0.));
+        assert_eq!(record.normal, Vec3::new(-1., 0., 0.));
+        assert!(record.front_face);
+
+        // Normals stay perpendicular to the stretched surface
+        let ray = Ray::new(Point3::new(11., 5., 0.), Vec3::new(0., -1., 0.));
+        let record = instance.hit(&ray, ray_t).unwrap();
+        let local = record.p - Point3::new(10., 0., 0.);
+        let expected = Vec3::new(local.x / 4., local.y, local.z).normalize();
+        assert!((record.normal - expected).length() < 1e-12);
+
+        // The original object is left where it was
+        assert!(sphere.hit(&ray, ray_t).is_none());
+        assert_eq!(Arc::strong_count(&sphere), 2);
+
+        // Flattened out of existence
+        let flat = Matrix4::scaling(Vec3::new(1e-13, 1., 1.));
+        assert!(Instance::new(sphere, flat).is_err());
+    }
+
+    #[test]
+    fn test_rotated_instance() {
+        let triangle: Arc<dyn Hittable> = Arc::new(Triangle::new(
+            Point3::new(0., 0., 0.),
+            Point3::new(1., 0., 0.),
+            Point3::new(0., 1., 0.),
+            MATERIAL_CONCRETE.clone(),
+        ));
+        // Facing +x instead of +z
+        let instance =
+            Instance::new(triangle, Matrix4::rotation(Vec3::new(0., 1., 0.), 90.)).unwrap();
+        let ray = Ray::new(Point3::new(5., 0.25, -0.25), Vec3::new(-1., 0., 0.));
+        let record = instance
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((record.t - 5.).abs() < 1e-12);
+        assert!((record.normal - Vec3::new(1., 0., 0.)).length() < 1e-12);
+    }
+}
@@ -0,0 +1,591 @@
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable, Light};
+use crate::hittable_list::LightList;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rng::SampleRng;
+use crate::stats::{PathEnd, PathStats};
+use crate::vec::{Onb, Vec3};
+
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Computes the value of a camera sample. The camera provides the lights, the background and
+// the path settings.
+pub trait Integrator: Send + Sync {
+    // Tells integrators apart, e.g. in checkpoints
+    fn name(&self) -> &'static str;
+
+    // Parameters that change the value of samples, checkpoints are only resumed with the same
+    fn settings(&self) -> Vec<f64>;
+
+    fn ray_color(
+        &self,
+        camera: &Camera,
+        rng: &mut SampleRng,
+        ray: &Ray,
+        world: &dyn Hittable,
+        stats: &mut PathStats,
+    ) -> Color;
+}
+
+// Unidirectional path tracer with light sampling, the one that renders the actual image
+pub struct PathTracer;
+
+impl PathTracer {
+    // Next event estimation: light arriving from a random point of the lights, weighted
+    // against finding the same light by scattering
+    fn sample_lights(
+        lights: &LightList,
+        rng: &mut SampleRng,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        world: &dyn Hittable,
+        stats: &mut PathStats,
+    ) -> Color {
+        if lights.objects.is_empty() {
+            return Color::black();
+        }
+        let direction = lights.random(rng, &hit_record.p);
+        let light_ray = Ray::with_time(hit_record.p, direction, ray.time);
+        let light_pdf = lights.pdf_value(&hit_record.p, &direction);
+        let (wi, wo) = (direction.normalize(), -ray.direction.normalize());
+        let scattering_pdf = hit_record.material.pdf(hit_record, &wi, &wo);
+        if light_pdf <= 0. || scattering_pdf <= 0. {
+            return Color::black();
+        }
+
+        let Some(light_record) = lights.hit(&light_ray, Interval::new(0.001, f64::INFINITY)) else {
+            return Color::black();
+        };
+        // Anything in front of the light casts a shadow
+        stats.shadow_rays += 1;
+        let shadow_t = Interval::new(0.001, light_record.t * (1. - 1e-9));
+        if world.hit(&light_ray, shadow_t).is_some() {
+            return Color::black();
+        }
+
+        let emitted = light_record.material.emitted(&light_ray, &light_record);
+        let response = hit_record.material.eval(hit_record, &wi, &wo);
+        return power_heuristic(light_pdf, scattering_pdf) / light_pdf * response * emitted;
+    }
+
+    // Next event estimation for backgrounds that can be importance sampled: light arriving
+    // from a direction where the background is bright, if nothing is in the way
+    fn sample_background(
+        background: &dyn Background,
+        rng: &mut SampleRng,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        world: &dyn Hittable,
+        stats: &mut PathStats,
+    ) -> Color {
+        let Some((direction, background_pdf)) = background.sample(rng) else {
+            return Color::black();
+        };
+        let wo = -ray.direction.normalize();
+        let scattering_pdf = hit_record.material.pdf(hit_record, &direction, &wo);
+        if scattering_pdf <= 0. {
+            return Color::black();
+        }
+
+        let background_ray = Ray::with_time(hit_record.p, direction, ray.time);
+        stats.shadow_rays += 1;
+        if world
+            .hit(&background_ray, Interval::new(0.001, f64::INFINITY))
+            .is_some()
+        {
+            return Color::black();
+        }
+
+        let response = hit_record.material.eval(hit_record, &direction, &wo);
+        return power_heuristic(background_pdf, scattering_pdf) / background_pdf
+            * response
+            * background.value(&direction);
+    }
+}
+
+impl Integrator for PathTracer {
+    fn name(&self) -> &'static str {
+        "path"
+    }
+
+    fn settings(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    // Radiance arriving along the camera ray, following a single path
+    fn ray_color(
+        &self,
+        camera: &Camera,
+        rng: &mut SampleRng,
+        camera_ray: &Ray,
+        world: &dyn Hittable,
+        stats: &mut PathStats,
+    ) -> Color {
+        let mut color = Color::black();
+        // Fraction of the light found further along the path that reaches the camera
+        let mut throughput = Color::white();
+        let mut ray = *camera_ray;
+        // Density of the bounce that sent `ray`, None for camera rays and specular bounces
+        let mut scattering_pdf: Option<f64> = None;
+        let mut bounces = 0;
+
+        loop {
+            let Some(hit_record) = world.hit(&ray, Interval::new(0.001, f64::INFINITY)) else {
+                let mut background = camera.background.value(&ray.direction);
+                // The background could have been sampled directly as well
+                if let Some(pdf) = scattering_pdf {
+                    let background_pdf = camera.background.pdf(&ray.direction);
+                    background = power_heuristic(pdf, background_pdf) * background;
+                }
+                color += throughput * background;
+                stats.add_path(bounces, PathEnd::Escaped);
+                return color;
+            };
+
+            let mut emitted = hit_record.material.emitted(&ray, &hit_record);
+            // Lights found by scattering could have been sampled directly as well
+            if let Some(pdf) = scattering_pdf {
+                if emitted != Color::black() {
+                    let light_pdf = camera.lights.pdf_value(&ray.origin, &ray.direction);
+                    emitted = power_heuristic(pdf, light_pdf) * emitted;
+                }
+            }
+            color += throughput * emitted;
+
+            let material = hit_record.material;
+            let wo = -ray.direction.normalize();
+            let Some(sample) = material.sample(rng, &hit_record, &wo) else {
+                stats.add_path(bounces, PathEnd::Absorbed);
+                return color;
+            };
+            if material.is_specular() {
+                // Sampling the lights can't help
+                scattering_pdf = None;
+            } else {
+                let lights = &camera.lights;
+                color +=
+                    throughput * Self::sample_lights(lights, rng, &ray, &hit_record, world, stats);
+                let background = &*camera.background;
+                color += throughput
+                    * Self::sample_background(background, rng, &ray, &hit_record, world, stats);
+                scattering_pdf = Some(sample.pdf);
+            }
+            throughput = throughput * sample.weight;
+            ray = Ray::with_time(hit_record.p, sample.direction, ray.time);
+            bounces += 1;
+
+            if camera.max_depth > 0 && bounces >= camera.max_depth {
+                stats.add_path(bounces, PathEnd::MaxDepth);
+                return color;
+            }
+            // Paths survive in proportion to the light they can still carry, and the survivors
+            // make up for the others. The survival probability is capped so that paths between
+            // mirrors or through glass end as well.
+            if bounces >= camera.russian_roulette_depth {
+                let survival = throughput.max_component().min(0.95);
+                if rng.gen::<f64>() >= survival {
+                    stats.add_path(bounces, PathEnd::RussianRoulette);
+                    return color;
+                }
+                throughput = throughput / survival;
+            }
+        }
+    }
+}
+
+// Multiple importance sampling weight of a direction picked with density `pdf` when another
+// strategy would have picked it with density `other_pdf` (Veach, 1997)
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let squared = pdf * pdf;
+    return squared / (squared + other_pdf * other_pdf);
+}
+
+// The integrators below are debug views, showing the first hit of the camera rays in black
+// when nothing is hit
+
+// Shading normals, with their x, y and z mapped to red, green and blue
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn name(&self) -> &'static str {
+        "normals"
+    }
+
+    fn settings(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    fn ray_color(
+        &self,
+        _camera: &Camera,
+        _rng: &mut SampleRng,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _stats: &mut PathStats,
+    ) -> Color {
+        let Some(hit_record) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) else {
+            return Color::black();
+        };
+        return 0.5 * (hit_record.normal + Color::white());
+    }
+}
+
+// Distance to the camera, from white up close to black at `far` and beyond
+pub struct Depth {
+    pub far: f64,
+}
+
+impl Integrator for Depth {
+    fn name(&self) -> &'static str {
+        "depth"
+    }
+
+    fn settings(&self) -> Vec<f64> {
+        vec![self.far]
+    }
+
+    fn ray_color(
+        &self,
+        _camera: &Camera,
+        _rng: &mut SampleRng,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _stats: &mut PathStats,
+    ) -> Color {
+        let Some(hit_record) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) else {
+            return Color::black();
+        };
+        let distance = hit_record.t * ray.direction.length();
+        return (1. - distance / self.far).max(0.) * Color::white();
+    }
+}
+
+// Color of the surfaces, the weight of a single bounce off them. Lights show their emission.
+pub struct Albedo;
+
+impl Integrator for Albedo {
+    fn name(&self) -> &'static str {
+        "albedo"
+    }
+
+    fn settings(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    fn ray_color(
+        &self,
+        _camera: &Camera,
+        rng: &mut SampleRng,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _stats: &mut PathStats,
+    ) -> Color {
+        let Some(hit_record) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) else {
+            return Color::black();
+        };
+        let wo = -ray.direction.normalize();
+        return match hit_record.material.sample(rng, &hit_record, &wo) {
+            Some(sample) => sample.weight,
+            None => hit_record.material.emitted(ray, &hit_record),
+        };
+    }
+}
+
+// Fraction of the hemisphere above the surface that is not blocked within `radius`,
+// weighted by the cosine
+pub struct AmbientOcclusion {
+    pub radius: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn name(&self) -> &'static str {
+        "ao"
+    }
+
+    fn settings(&self) -> Vec<f64> {
+        vec![self.radius]
+    }
+
+    fn ray_color(
+        &self,
+        _camera: &Camera,
+        rng: &mut SampleRng,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _stats: &mut PathStats,
+    ) -> Color {
+        let Some(hit_record) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) else {
+            return Color::black();
+        };
+        let direction = Onb::new(hit_record.normal).to_world(Vec3::random_cosine_direction(rng));
+        let occlusion_ray = Ray::with_time(hit_record.p, direction, ray.time);
+        if world
+            .hit(&occlusion_ray, Interval::new(0.001, self.radius))
+            .is_some()
+        {
+            return Color::black();
+        }
+        return Color::white();
+    }
+}
+
+// A random color for each material, picked from its position among the given materials so that
+// it stays the same from one run to the next. Other materials, e.g. those of MTL files, are
+// white.
+pub struct MaterialId {
+    // Position of each material, by address
+    ids: HashMap<usize, u64>,
+}
+
+impl MaterialId {
+    pub fn new<'a>(materials: impl IntoIterator<Item = &'a Arc<dyn Material>>) -> Self {
+        let ids = materials
+            .into_iter()
+            .enumerate()
+            .map(|(id, material)| (address(&**material), id as u64))
+            .collect();
+        MaterialId { ids }
+    }
+}
+
+fn address(material: &dyn Material) -> usize {
+    material as *const dyn Material as *const () as usize
+}
+
+impl Integrator for MaterialId {
+    fn name(&self) -> &'static str {
+        "material-id"
+    }
+
+    fn settings(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    fn ray_color(
+        &self,
+        _camera: &Camera,
+        _rng: &mut SampleRng,
+        ray: &Ray,
+        world: &dyn Hittable,
+        _stats: &mut PathStats,
+    ) -> Color {
+        let Some(hit_record) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) else {
+            return Color::black();
+        };
+        let Some(&id) = self.ids.get(&address(hit_record.material)) else {
+            return Color::white();
+        };
+        let mut color_rng = SampleRng::seed_from_u64(id);
+        return Color::random(&mut color_rng);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::EnvironmentMap;
+    use crate::hittable_list::HittableList;
+    use crate::material::{DiffuseLight, Lambertian, MATERIAL_CONCRETE, MATERIAL_GROUND};
+    use crate::quad::Plane;
+    use crate::sphere::Sphere;
+    use crate::stats::RunningVariance;
+    use crate::vec::Point3;
+    use std::f64::consts::PI;
+
+    // Unit sphere resting on the ground
+    fn ball_on_ground() -> HittableList {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., 1., 0.),
+            1.,
+            MATERIAL_CONCRETE.clone(),
+        )));
+        world.add(Box::new(Plane::new(
+            Point3::zeros(),
+            Vec3::new(0., 1., 0.),
+            MATERIAL_GROUND.clone(),
+        )));
+        return world;
+    }
+
+    fn first_hit(integrator: &dyn Integrator, rng: &mut SampleRng, ray: &Ray) -> Color {
+        let mut stats = PathStats::default();
+        let color =
+            integrator.ray_color(&Camera::default(), rng, ray, &ball_on_ground(), &mut stats);
+        // Only path tracing counts paths
+        assert_eq!(stats.paths(), 0);
+        return color;
+    }
+
+    #[test]
+    fn test_debug_views() {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let down = Ray::new(Point3::new(0., 5., 0.), Vec3::new(0., -1., 0.));
+        let sky = Ray::new(Point3::new(0., 5., 0.), Vec3::new(0., 1., 0.));
+
+        assert_eq!(
+            first_hit(&Normals, &mut rng, &down),
+            Color::new(0.5, 1., 0.5)
+        );
+        assert_eq!(first_hit(&Normals, &mut rng, &sky), Color::black());
+
+        let depth = Depth { far: 12. };
+        assert_eq!(
+            first_hit(&depth, &mut rng, &down),
+            Color::new(0.75, 0.75, 0.75)
+        );
+        let far_away = Ray::new(Point3::new(20., 50., 0.), Vec3::new(0., -1., 0.));
+        assert_eq!(first_hit(&depth, &mut rng, &far_away), Color::black());
+
+        // Diffuse surfaces weigh their bounces by their albedo
+        let albedo = first_hit(&Albedo, &mut rng, &down);
+        assert!((albedo - Color::new(0.5, 0.5, 0.5)).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let occlusion = |rng: &mut SampleRng, x: f64, radius: f64| {
+            let ray = Ray::new(Point3::new(x, 5., 0.1), Vec3::new(0., -1., 0.));
+            let ao = AmbientOcclusion { radius };
+            let n_samples = 2000;
+            let visible: f64 = (0..n_samples).map(|_| first_hit(&ao, rng, &ray).x).sum();
+            return visible / n_samples as f64;
+        };
+
+        // Out in the open, or with a radius too small to reach the ball, nothing is blocked
+        assert_eq!(occlusion(&mut rng, 10., 1.), 1.);
+        assert_eq!(occlusion(&mut rng, 1.2, 0.01), 1.);
+        // Next to the ball, part of the sky is hidden
+        let next_to_ball = occlusion(&mut rng, 1.2, 10.);
+        assert!(next_to_ball > 0.2 && next_to_ball < 0.9, "{}", next_to_ball);
+    }
+
+    #[test]
+    fn test_shadow_rays_are_counted() {
+        let world = ball_on_ground();
+        let mut camera = Camera::default();
+        let mut rng = SampleRng::seed_from_u64(0);
+        let ray = Ray::new(Point3::new(3., 5., 0.), Vec3::new(0., -1., 0.));
+        let trace = |camera: &Camera, rng: &mut SampleRng| {
+            let mut stats = PathStats::default();
+            for _ in 0..100 {
+                PathTracer.ray_color(camera, rng, &ray, &world, &mut stats);
+            }
+            return stats;
+        };
+
+        // Nothing to sample directly
+        assert_eq!(trace(&camera, &mut rng).shadow_rays, 0);
+        // At most one shadow ray for each diffuse bounce
+        camera.lights.add(Box::new(Sphere::new(
+            Point3::new(0., 5., 0.),
+            1.,
+            Arc::new(DiffuseLight::new(Color::white())),
+        )));
+        let stats = trace(&camera, &mut rng);
+        assert!(stats.shadow_rays > 0 && stats.shadow_rays <= stats.total_bounces);
+    }
+
+    #[test]
+    fn test_material_ids() {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let ball = Ray::new(Point3::new(0., 5., 0.), Vec3::new(0., -1., 0.));
+        let ball_again = Ray::new(Point3::new(0.2, 5., 0.), Vec3::new(0., -1., 0.));
+        let ground = Ray::new(Point3::new(10., 5., 0.), Vec3::new(0., -1., 0.));
+        let materials: [Arc<dyn Material>; 2] =
+            [MATERIAL_CONCRETE.clone(), MATERIAL_GROUND.clone()];
+        let material_id = MaterialId::new(&materials);
+        let ball_color = first_hit(&material_id, &mut rng, &ball);
+        assert_eq!(first_hit(&material_id, &mut rng, &ball_again), ball_color);
+        assert_ne!(first_hit(&material_id, &mut rng, &ground), ball_color);
+
+        // Colors only depend on the position of the material, not on where it is in memory
+        assert_eq!(ball_color, Color::random(&mut SampleRng::seed_from_u64(0)));
+        let material_id = MaterialId::new(&materials[1..]);
+        assert_eq!(first_hit(&material_id, &mut rng, &ball), Color::white());
+    }
+
+    // Forwards the radiance of a background but can't be sampled
+    struct Unsampled(EnvironmentMap);
+
+    impl Background for Unsampled {
+        fn value(&self, direction: &Vec3) -> Color {
+            self.0.value(direction)
+        }
+    }
+
+    #[test]
+    fn test_environment_map_light_sampling() {
+        // Dim sky with a small bright sun, over a grey ground
+        let (width, height) = (8, 4);
+        let environment_map = || {
+            let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+            pixels[width + 3] = Color::new(50., 50., 50.);
+            return EnvironmentMap::new(width, height, pixels);
+        };
+        let mut world = HittableList::new();
+        world.add(Box::new(Plane::new(
+            Point3::zeros(),
+            Vec3::new(0., 1., 0.),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+
+        // Cosine weighted light of the pixels of the upper half, each row covering the
+        // polar angles from theta0 to theta1
+        let map = environment_map();
+        let mut irradiance = 0.;
+        for y in 0..height / 2 {
+            let theta0 = PI * y as f64 / height as f64;
+            let theta1 = PI * (y + 1) as f64 / height as f64;
+            let cos_solid_angle =
+                (2. * PI / width as f64) * (theta1.sin().powi(2) - theta0.sin().powi(2)) / 2.;
+            for x in 0..width {
+                let phi = 2. * PI * ((x as f64 + 0.5) / width as f64 - 0.5);
+                let theta = 0.5 * (theta0 + theta1);
+                let direction = Vec3::new(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    -theta.sin() * phi.cos(),
+                );
+                irradiance += map.value(&direction).x * cos_solid_angle;
+            }
+        }
+        let expected = 0.5 / PI * irradiance;
+
+        let estimate = |camera: &Camera| {
+            let mut rng = SampleRng::seed_from_u64(3);
+            let mut stats = PathStats::default();
+            let mut variance = RunningVariance::default();
+            let ray = Ray::new(Point3::new(0., 5., 0.), Vec3::new(0., -1., 0.));
+            for _ in 0..20_000 {
+                let color = PathTracer.ray_color(camera, &mut rng, &ray, &world, &mut stats);
+                variance.add(color.x);
+            }
+            return variance;
+        };
+        let mut camera = Camera::default();
+        camera.background = Box::new(environment_map());
+        let sampled = estimate(&camera);
+        camera.background = Box::new(Unsampled(environment_map()));
+        let unsampled = estimate(&camera);
+
+        // Both are right, sampling the sun directly is a lot less noisy
+        for variance in [&sampled, &unsampled] {
+            let error = (variance.mean - expected).abs();
+            assert!(
+                error < 5. * variance.standard_error(),
+                "{} vs {}",
+                variance.mean,
+                expected
+            );
+        }
+        assert!(sampled.standard_error() < 0.25 * unsampled.standard_error());
+    }
+}
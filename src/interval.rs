@@ -1,4 +1,4 @@
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Interval {
     pub lower: f64,
     pub upper: f64,
@@ -18,6 +18,33 @@ impl Interval {
         Interval { lower, upper }
     }
 
+    pub const fn empty() -> Self {
+        Interval {
+            lower: f64::INFINITY,
+            upper: f64::NEG_INFINITY,
+        }
+    }
+
+    // Smallest interval containing both intervals
+    pub fn enclosing(a: &Interval, b: &Interval) -> Self {
+        Interval {
+            lower: a.lower.min(b.lower),
+            upper: a.upper.max(b.upper),
+        }
+    }
+
+    pub fn size(&self) -> f64 {
+        self.upper - self.lower
+    }
+
+    pub fn expand(&self, delta: f64) -> Self {
+        let padding = 0.5 * delta;
+        Interval {
+            lower: self.lower - padding,
+            upper: self.upper + padding,
+        }
+    }
+
     pub fn contains(&self, x: f64) -> bool {
         self.lower <= x && x <= self.upper
     }
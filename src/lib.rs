@@ -1,10 +1,33 @@
+#![allow(clippy::needless_return)]
+
+pub mod aabb;
+pub mod background;
+pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
 pub mod color;
+pub mod framebuffer;
 pub mod hittable;
 pub mod hittable_list;
+pub mod image_reader;
+pub mod image_writer;
+pub mod instance;
+pub mod integrator;
 pub mod interval;
 pub mod material;
+pub mod matrix;
+pub mod medium;
+pub mod mesh;
+pub mod obj;
+pub mod progress;
+pub mod quad;
 pub mod ray;
+pub mod rng;
+pub mod scene;
 pub mod sphere;
+pub mod stats;
+pub mod texture;
+pub mod tiles;
+pub mod triangle;
 pub mod utils;
 pub mod vec;
@@ -1,81 +1,387 @@
-use eerdekens_bot::hittable_list::HittableList;
-use eerdekens_bot::sphere::Sphere;
-use eerdekens_bot::vec::Point3;
+#![allow(clippy::needless_return)]
 
+use clap::{Parser, ValueEnum};
+use eerdekens_bot::bvh::BvhNode;
 use eerdekens_bot::camera::Camera;
-use eerdekens_bot::material;
-use rand::Rng;
-
-fn add_three_balls_on_ground_scene(world: &mut HittableList) {
-    world.add(Box::new(Sphere::new(
-        Point3::new(0., 1., 0.),
-        1.,
-        &material::MATERIAL_COPPER,
-    )));
-    world.add(Box::new(Sphere::new(
-        Point3::new(-4., 1., 0.),
-        1.,
-        &material::MATERIAL_RED_PLASTIC,
-    )));
-    world.add(Box::new(Sphere::new(
-        Point3::new(4., 1., 0.),
-        1.,
-        &material::MATERIAL_GLASS,
-    )));
-
-    world.add(Box::new(Sphere::new(
-        Point3::new(0., -1000., 0.),
-        1000.,
-        &material::MATERIAL_CONCRETE,
-    )));
+use eerdekens_bot::checkpoint::Checkpoint;
+use eerdekens_bot::image_writer::ImageFormat;
+use eerdekens_bot::integrator::{
+    Albedo, AmbientOcclusion, Depth, Integrator, MaterialId, Normals, PathTracer,
+};
+use eerdekens_bot::material::Material;
+use eerdekens_bot::progress::{Progress, ProgressReporter};
+use eerdekens_bot::scene;
+use eerdekens_bot::tiles::TileOrder;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Ppm,
+    PpmBinary,
+    Png,
+}
+
+impl From<OutputFormat> for ImageFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Ppm => ImageFormat::PpmAscii,
+            OutputFormat::PpmBinary => ImageFormat::PpmBinary,
+            OutputFormat::Png => ImageFormat::Png,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum IntegratorKind {
+    /// Path tracing, the actual image
+    Path,
+    /// Shading normals as colors
+    Normals,
+    /// Distance to the camera, white up close
+    Depth,
+    /// Surface colors
+    Albedo,
+    /// Ambient occlusion
+    Ao,
+    /// A random color per material
+    MaterialId,
+}
+
+impl IntegratorKind {
+    // Debug views are scaled to the distance between the camera and what it looks at
+    fn integrator(
+        self,
+        camera: &Camera,
+        materials: &BTreeMap<String, Arc<dyn Material>>,
+    ) -> Box<dyn Integrator> {
+        let scale = (camera.lookat - camera.lookfrom).length();
+        match self {
+            IntegratorKind::Path => Box::new(PathTracer),
+            IntegratorKind::Normals => Box::new(Normals),
+            IntegratorKind::Depth => Box::new(Depth { far: 2. * scale }),
+            IntegratorKind::Albedo => Box::new(Albedo),
+            IntegratorKind::Ao => Box::new(AmbientOcclusion {
+                radius: 0.1 * scale,
+            }),
+            IntegratorKind::MaterialId => Box::new(MaterialId::new(materials.values())),
+        }
+    }
 }
 
-fn add_random_balls(world: &mut HittableList, n_balls: usize) {
-    let mut rng = rand::thread_rng();
-    for _ in 0..n_balls {
-        let a = rng.gen_range(-5..5);
-        let b = rng.gen_range(-5..5);
-        let material = material::random_static_material(&mut rng);
-        let center = Point3::new(
-            a as f64 + 0.9 * rng.gen::<f64>(),
-            0.2,
-            b as f64 + 0.9 * rng.gen::<f64>(),
+#[derive(Clone, Copy, ValueEnum)]
+enum TileOrderArg {
+    /// Outwards from the center
+    Spiral,
+    /// Along a Hilbert curve
+    Hilbert,
+}
+
+impl From<TileOrderArg> for TileOrder {
+    fn from(order: TileOrderArg) -> Self {
+        match order {
+            TileOrderArg::Spiral => TileOrder::Spiral,
+            TileOrderArg::Hilbert => TileOrder::Hilbert,
+        }
+    }
+}
+
+// Hours, minutes and seconds, without the hours if there are none
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        return format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
         );
-        let radius = 0.2;
-        if (center - Point3::new(4., 0.2, 0.)).length() > 0.9 {
-            world.add(Box::new(Sphere::new(center, radius, material)));
+    }
+    return format!("{}:{:02}", seconds / 60, seconds % 60);
+}
+
+// Progress bar on stderr, redrawn at most ten times a second
+struct ProgressBar {
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl ProgressReporter for ProgressBar {
+    fn tile_done(&self, progress: &Progress) {
+        let mut last_draw = self.last_draw.lock().unwrap();
+        let finished = progress.tiles_done == progress.tiles_total;
+        if !finished && last_draw.is_some_and(|time| time.elapsed() < Duration::from_millis(100)) {
+            return;
         }
+        *last_draw = Some(Instant::now());
+
+        let width = 30;
+        let filled = ((progress.fraction() * width as f64).round() as usize).min(width);
+        let eta = match progress.eta() {
+            Some(eta) => format_duration(eta),
+            None => "?".to_string(),
+        };
+        eprint!(
+            "\r[{}{}] {:5.1}% {}/{} tiles, {:.2} Mrays/s, {} left   ",
+            "#".repeat(filled),
+            "-".repeat(width - filled),
+            100. * progress.fraction(),
+            progress.tiles_done,
+            progress.tiles_total,
+            progress.rays_per_second() / 1e6,
+            eta
+        );
+    }
+}
+
+/// Render a scene description to an image.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Scene description (TOML)
+    #[arg(default_value = "scenes/three_balls_on_ground.toml")]
+    scene: String,
+
+    /// Output image, the format is guessed from the extension unless --format is given
+    #[arg(short, long, default_value = "render.png")]
+    output: String,
+
+    /// Output image format
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Image width in pixels, overrides the scene
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+
+    /// Image height in pixels, overrides the scene
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+
+    /// Samples per pixel, overrides the scene
+    #[arg(short, long, value_parser = clap::value_parser!(i32).range(1..))]
+    samples: Option<i32>,
+
+    /// Fewest samples per pixel with adaptive sampling, overrides the scene
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    min_samples: Option<i32>,
+
+    /// Noise level under which pixels stop taking samples, 0 to always take them all,
+    /// overrides the scene
+    #[arg(long, value_parser = non_negative)]
+    noise_threshold: Option<f64>,
+
+    /// Also write an image of the number of samples taken for each pixel
+    #[arg(long)]
+    heatmap: Option<String>,
+
+    /// Samples per pixel of each pass, the output image is written again after every pass
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    samples_per_pass: Option<i32>,
+
+    /// Save the state of the render to this file every now and then, to resume it if it gets
+    /// interrupted
+    #[arg(long)]
+    checkpoint: Option<String>,
+
+    /// Seconds between two checkpoints
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+
+    /// Pick up the render from the checkpoint, if there is one
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+
+    /// Size of the square tiles rendered in parallel, in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    tile_size: Option<u32>,
+
+    /// Order in which the tiles are rendered
+    #[arg(long, value_enum, default_value = "spiral")]
+    tile_order: TileOrderArg,
+
+    /// Maximum number of bounces per ray, 0 for no limit, overrides the scene
+    #[arg(short, long)]
+    depth: Option<u32>,
+
+    /// What to render, debug views are much faster than path tracing
+    #[arg(short, long, value_enum, default_value = "path")]
+    integrator: IntegratorKind,
+
+    /// Seed of the random samples, renders with the same seed are identical
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+}
+
+fn fail<E: std::fmt::Display>(path: &str, error: E) -> ! {
+    eprintln!("{}: {}", path, error);
+    std::process::exit(1);
+}
+
+fn non_negative(value: &str) -> Result<f64, String> {
+    let value: f64 = value.parse().map_err(|error| format!("{}", error))?;
+    if value.is_nan() || value < 0. {
+        return Err(format!("must not be negative, got {}", value));
     }
+    return Ok(value);
 }
 
 fn main() {
-    let image_path = "raytracing_level_release_Hinata.ppm";
-
-    // World
-    // TODO(geoff): Take world from a config file
-    let mut world = HittableList::new();
-    add_three_balls_on_ground_scene(&mut world);
-    match 0 {
-        0 => (),
-        1 => add_random_balls(&mut world, 100),
-        _ => todo!("Haven't implemented this variant yet!"),
+    let args = Args::parse();
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .unwrap();
+    }
+
+    let scene = match scene::load_scene(&args.scene) {
+        Ok(scene) => scene,
+        Err(error) => fail(&args.scene, error),
     };
 
-    // Camera
-    let mut camera = Camera::default();
-    camera.aspect_ratio = 16.0 / 9.0;
-    camera.image_width = 1200;
+    let mut camera = scene.camera;
+    match (args.width, args.height) {
+        (Some(width), Some(height)) => camera.set_image_size(width as usize, height as usize),
+        (Some(width), None) => camera.image_width = width as usize,
+        (None, Some(height)) => {
+            let width = (height as f64 * camera.aspect_ratio).round().max(1.) as usize;
+            camera.set_image_size(width, height as usize);
+        }
+        (None, None) => (),
+    }
+    if let Some(samples) = args.samples {
+        camera.num_samples_per_pixel = samples;
+    }
+    if let Some(min_samples) = args.min_samples {
+        camera.min_samples_per_pixel = min_samples;
+    }
+    if let Some(noise_threshold) = args.noise_threshold {
+        camera.noise_threshold = noise_threshold;
+    }
+    if let Some(depth) = args.depth {
+        camera.max_depth = depth;
+    }
+    if let Some(seed) = args.seed {
+        camera.seed = seed;
+    }
+    camera.integrator = args.integrator.integrator(&camera, &scene.materials);
+
+    let format = args
+        .format
+        .map(ImageFormat::from)
+        .or_else(|| ImageFormat::from_path(&args.output))
+        .unwrap_or(ImageFormat::PpmAscii);
+
+    if let Some(samples_per_pass) = args.samples_per_pass {
+        camera.samples_per_pass = samples_per_pass;
+    }
+    if let Some(tile_size) = args.tile_size {
+        camera.tile_size = tile_size as usize;
+    }
+    camera.tile_order = args.tile_order.into();
+    let show_progress = std::io::stderr().is_terminal();
+    if show_progress {
+        camera.progress = Box::new(ProgressBar {
+            last_draw: Mutex::new(None),
+        });
+    }
+
+    // Ctrl-C stops the render after the tiles being rendered, and still writes the image and
+    // the checkpoint. A second one doesn't wait.
+    let cancel = camera.cancel.clone();
+    if let Err(error) = ctrlc::set_handler(move || {
+        if cancel.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+    }) {
+        eprintln!("Can't handle Ctrl-C: {}", error);
+    }
 
-    camera.num_samples_per_pixel = 500;
-    camera.max_depth = 50;
+    let mut checkpoint = camera.new_checkpoint();
+    if let Some(checkpoint_path) = &args.checkpoint {
+        if args.resume && Path::new(checkpoint_path).exists() {
+            checkpoint = match Checkpoint::load(checkpoint_path) {
+                Ok(checkpoint) => checkpoint,
+                Err(error) => fail(checkpoint_path, error),
+            };
+            if let Err(message) = camera.check_checkpoint(&checkpoint) {
+                fail(
+                    checkpoint_path,
+                    format!("can't resume the render, {}", message),
+                );
+            }
+            eprintln!(
+                "Resuming from {} samples per pixel",
+                checkpoint.samples_per_pixel
+            );
+        }
+    }
 
-    camera.vfov = 20.0;
-    camera.lookfrom = Point3::new(13., 2., 3.);
-    camera.lookat = Point3::new(0., 0., 0.);
-    camera.v_up = Point3::new(0., 1., 0.);
+    let world = BvhNode::new(scene.world);
+    let checkpoint_interval = Duration::from_secs(args.checkpoint_interval);
+    let mut last_checkpoint = Instant::now();
+    let rendered = camera.render_passes(&world, &mut checkpoint, |checkpoint| {
+        if let Err(error) = checkpoint.framebuffer.write_image(&args.output, format) {
+            fail(&args.output, error);
+        }
+        if let Some(checkpoint_path) = &args.checkpoint {
+            if last_checkpoint.elapsed() >= checkpoint_interval {
+                if let Err(error) = checkpoint.save(checkpoint_path) {
+                    fail(checkpoint_path, error);
+                }
+                last_checkpoint = Instant::now();
+            }
+        }
+    });
+    if show_progress {
+        eprintln!();
+    }
+    let finished = match rendered {
+        Ok(finished) => finished,
+        Err(message) => fail(&args.scene, format!("can't render, {}", message)),
+    };
+    if !finished {
+        eprintln!(
+            "Render cancelled after {} samples per pixel",
+            checkpoint.samples_per_pixel
+        );
+    }
 
-    camera.defocus_angle = 0.6;
-    camera.focus_dist = 10.;
+    let framebuffer = &checkpoint.framebuffer;
+    if let Err(error) = framebuffer.write_image(&args.output, format) {
+        fail(&args.output, error);
+    }
+    if let Some(checkpoint_path) = &args.checkpoint {
+        if let Err(error) = checkpoint.save(checkpoint_path) {
+            fail(checkpoint_path, error);
+        }
+    }
+    if let Some(heatmap) = &args.heatmap {
+        let heatmap_format = ImageFormat::from_path(heatmap).unwrap_or(ImageFormat::PpmAscii);
+        if let Err(error) = framebuffer.write_heatmap(heatmap, heatmap_format) {
+            fail(heatmap, error);
+        }
+    }
 
-    camera.render(&world, image_path);
+    if camera.noise_threshold > 0. {
+        eprintln!(
+            "{:.1} samples per pixel on average",
+            framebuffer.mean_sample_count()
+        );
+    }
+    if camera.path_stats().paths() > 0 {
+        eprintln!("{}", camera.path_stats());
+    }
+    if !finished {
+        std::process::exit(130);
+    }
 }
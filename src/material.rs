@@ -1,9 +1,14 @@
 use crate::color::Color;
 use crate::hittable::HitRecord;
 use crate::ray::Ray;
-use crate::vec::Vec3;
+use crate::rng::SampleRng;
+use crate::texture::{SolidColor, Texture};
+use crate::vec::{Onb, Vec3};
 
+use lazy_static::lazy_static;
 use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub enum MaterialType {
     Lambertian,
@@ -11,28 +16,112 @@ pub enum MaterialType {
     Dielectric,
 }
 
+// Direction picked by a material for the light it scatters
+pub struct BsdfSample {
+    // Unit direction the light comes from, away from the surface
+    pub direction: Vec3,
+    // `eval` over `pdf` for the sampled direction, the attenuation of the scattered light
+    pub weight: Color,
+    // 0 for specular materials
+    pub pdf: f64,
+}
+
+// Directions are unit vectors pointing away from the hit point: `wo` towards where the light
+// goes, e.g. the camera, and `wi` towards where it comes from
 pub trait Material: Send + Sync {
+    fn sample(&self, rng: &mut SampleRng, rec: &HitRecord, wo: &Vec3) -> Option<BsdfSample>;
+
+    // Fraction of the light arriving from `wi` scattered towards `wo`, including the cosine
+    // factor of surfaces
+    fn eval(&self, _rec: &HitRecord, _wi: &Vec3, _wo: &Vec3) -> Color {
+        Color::black()
+    }
+
+    // Density, over solid angles, of `sample` picking `wi`
+    fn pdf(&self, _rec: &HitRecord, _wi: &Vec3, _wo: &Vec3) -> f64 {
+        0.
+    }
+
+    // Specular materials scatter in a few exact directions, which can be sampled but not
+    // evaluated, so light sampling skips them
+    fn is_specular(&self) -> bool {
+        false
+    }
+
     fn scatter(
         &self,
-        rng: &mut rand::rngs::ThreadRng,
+        rng: &mut SampleRng,
         incoming_ray: &Ray,
         rec: &HitRecord,
-    ) -> Option<(Color, Ray)>;
+    ) -> Option<(Color, Ray)> {
+        let wo = -incoming_ray.direction.normalize();
+        let sample = self.sample(rng, rec, &wo)?;
+        let scattered_ray = Ray::with_time(rec.p, sample.direction, incoming_ray.time);
+        return Some((sample.weight, scattered_ray));
+    }
+
+    // Light emitted towards the incoming ray, black for everything but lights
+    fn emitted(&self, _incoming_ray: &Ray, _rec: &HitRecord) -> Color {
+        Color::black()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FresnelModel {
+    // Cheap polynomial fit, good enough for most dielectrics
+    Schlick,
+    // Unpolarized Fresnel equations
+    Exact,
+}
+
+impl FresnelModel {
+    // Fraction of light reflected at an interface, where cos_theta is the cosine of the
+    // incidence angle and refraction_ratio the ratio of the incident over transmitted indices
+    pub fn reflectance(&self, cos_theta: f64, refraction_ratio: f64) -> f64 {
+        match self {
+            FresnelModel::Schlick => {
+                let r0 = ((1. - refraction_ratio) / (1. + refraction_ratio)).powi(2);
+                return r0 + (1. - r0) * (1. - cos_theta).powi(5);
+            }
+            FresnelModel::Exact => {
+                let sin_theta_t = refraction_ratio * (1. - cos_theta * cos_theta).max(0.).sqrt();
+                if sin_theta_t >= 1. {
+                    // Total internal reflection
+                    return 1.;
+                }
+                let cos_theta_t = (1. - sin_theta_t * sin_theta_t).sqrt();
+                let r_s = (refraction_ratio * cos_theta - cos_theta_t)
+                    / (refraction_ratio * cos_theta + cos_theta_t);
+                let r_p = (cos_theta - refraction_ratio * cos_theta_t)
+                    / (cos_theta + refraction_ratio * cos_theta_t);
+                return 0.5 * (r_s * r_s + r_p * r_p);
+            }
+        }
+    }
 }
 
 pub struct Dielectric {
     pub refraction_index: f64,
+    pub fresnel: FresnelModel,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
-        Dielectric { refraction_index }
+        Dielectric {
+            refraction_index,
+            fresnel: FresnelModel::Schlick,
+        }
+    }
+
+    pub fn with_fresnel(refraction_index: f64, fresnel: FresnelModel) -> Self {
+        Dielectric {
+            refraction_index,
+            fresnel,
+        }
     }
 
-    fn reflect(&self, incoming_ray_direction: Vec3, rec: &HitRecord) -> Option<Ray> {
-        let reflected_direction = incoming_ray_direction.reflect(&rec.normal);
-        let reflected_ray = Ray::new(rec.p, reflected_direction);
-        return Some(reflected_ray);
+    fn reflect(&self, incoming_ray_direction: Vec3, rec: &HitRecord) -> Vec3 {
+        return incoming_ray_direction.reflect(&rec.normal);
     }
 
     fn refract(
@@ -40,7 +129,7 @@ impl Dielectric {
         incoming_ray_direction: Vec3,
         rec: &HitRecord,
         refraction_ratio: f64,
-    ) -> Option<Ray> {
+    ) -> Vec3 {
         let cos_theta = (-incoming_ray_direction.dot(&rec.normal)).min(1.0);
 
         let refracted_direction_orthogonal =
@@ -50,21 +139,12 @@ impl Dielectric {
             .sqrt()
             * rec.normal;
 
-        let refracted_direction = refracted_direction_parallel + refracted_direction_orthogonal;
-
-        let refracted_ray = Ray::new(rec.p, refracted_direction);
-
-        return Some(refracted_ray);
+        return refracted_direction_parallel + refracted_direction_orthogonal;
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(
-        &self,
-        _rng: &mut rand::rngs::ThreadRng,
-        incoming_ray: &Ray,
-        rec: &HitRecord,
-    ) -> Option<(Color, Ray)> {
+    fn sample(&self, rng: &mut SampleRng, rec: &HitRecord, wo: &Vec3) -> Option<BsdfSample> {
         let color = Color::new(1.0, 1.0, 1.0);
         let refraction_index_outside = 1.0;
 
@@ -75,102 +155,368 @@ impl Material for Dielectric {
         };
 
         // Determine if the material can refract at this angle
-        let unit_direction = incoming_ray.direction.normalize();
+        let unit_direction = -*wo;
         let cos_theta = (-unit_direction.dot(&rec.normal)).min(1.);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let scattered_ray: Option<Ray>;
-        // TODO(geoff): Implement Schlick's approximation for reflectance
-        if cannot_refract {
-            scattered_ray = self.reflect(unit_direction, rec);
+        // Reflect or refract at random, weighted by the Fresnel reflectance
+        let reflectance = self.fresnel.reflectance(cos_theta, refraction_ratio);
+        let direction = if cannot_refract || reflectance > rng.gen::<f64>() {
+            self.reflect(unit_direction, rec)
         } else {
-            scattered_ray = self.refract(unit_direction, rec, refraction_ratio);
-        }
-        return Some((color, scattered_ray.unwrap()));
-        // return scattered_ray.map(|ray| (color, ray));
+            self.refract(unit_direction, rec, refraction_ratio)
+        };
+        return Some(BsdfSample {
+            direction: direction.normalize(),
+            weight: color,
+            pdf: 0.,
+        });
+    }
+
+    fn is_specular(&self) -> bool {
+        true
     }
 }
 
-#[derive(Default)]
 pub struct Lambertian {
-    albedo: Color,
+    pub albedo: Arc<dyn Texture>,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Color) -> Self {
+        Lambertian::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Self {
+        Lambertian { albedo }
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(
-        &self,
-        rng: &mut rand::rngs::ThreadRng,
-        _incoming_ray: &Ray,
-        rec: &HitRecord,
-    ) -> Option<(Color, Ray)> {
-        let mut scatter_direction: Vec3 = rec.normal + Vec3::random_unit_vector(rng);
-        if scatter_direction.close_to(Vec3::zeros()) {
-            scatter_direction = rec.normal;
-        }
-        let scattered_ray = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.albedo;
-        return Some((attenuation, scattered_ray));
+    // Directions are cosine distributed around the normal, which cancels out with the cosine
+    // factor
+    fn sample(&self, rng: &mut SampleRng, rec: &HitRecord, _wo: &Vec3) -> Option<BsdfSample> {
+        let local = Vec3::random_cosine_direction(rng);
+        return Some(BsdfSample {
+            direction: Onb::new(rec.normal).to_world(local),
+            weight: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: local.z / PI,
+        });
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: &Vec3, _wo: &Vec3) -> Color {
+        let cosine = rec.normal.dot(wi).max(0.);
+        return cosine / PI * self.albedo.value(rec.u, rec.v, &rec.p);
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: &Vec3, _wo: &Vec3) -> f64 {
+        return rec.normal.dot(wi).max(0.) / PI;
     }
 }
 
-#[derive(Default)]
+// Conductor with a GGX microfacet distribution of normals. A roughness of 0 is a perfect mirror.
 pub struct Metal {
-    albedo: Color,
-    //TODO(geoff): add fuzziness
+    pub albedo: Arc<dyn Texture>,
+    pub roughness: f64,
+}
+
+impl Metal {
+    pub fn new(albedo: Color) -> Self {
+        Metal::with_roughness(albedo, 0.)
+    }
+
+    // Roughness is expected in [0, 1]
+    pub fn with_roughness(albedo: Color, roughness: f64) -> Self {
+        Metal::textured(Arc::new(SolidColor::new(albedo)), roughness)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, roughness: f64) -> Self {
+        Metal { albedo, roughness }
+    }
+
+    // Smith masking term for the GGX distribution, for a direction in the local shading frame
+    fn smith_lambda(alpha: f64, direction: Vec3) -> f64 {
+        let cos_theta_squared = direction.z * direction.z;
+        let tan_theta_squared = (1. - cos_theta_squared).max(0.) / cos_theta_squared;
+        return 0.5 * (-1. + (1. + alpha * alpha * tan_theta_squared).sqrt());
+    }
+
+    // GGX density of microfacet normals, for a normal in the local shading frame
+    fn distribution(alpha: f64, normal: Vec3) -> f64 {
+        let alpha_squared = alpha * alpha;
+        let denominator = normal.z * normal.z * (alpha_squared - 1.) + 1.;
+        return alpha_squared / (PI * denominator * denominator);
+    }
+
+    // Density of reflecting `outgoing` into `scattered` with a visible normal. The Jacobian of
+    // the reflection, 1 / (4 o.h), cancels out with the projected area of the microfacet.
+    fn reflection_pdf(alpha: f64, outgoing: Vec3, scattered: Vec3) -> f64 {
+        let half_vector = (outgoing + scattered).normalize();
+        let masking = 1. / (1. + Self::smith_lambda(alpha, outgoing));
+        return masking * Self::distribution(alpha, half_vector) / (4. * outgoing.z);
+    }
+
+    // Sample a microfacet normal among the ones visible from `outgoing`, both in the local
+    // shading frame (Heitz, "Sampling the GGX Distribution of Visible Normals", 2018)
+    fn sample_visible_normal(rng: &mut SampleRng, alpha: f64, outgoing: Vec3) -> Vec3 {
+        // Stretch the view direction to the hemisphere configuration
+        let v_h = Vec3::new(alpha * outgoing.x, alpha * outgoing.y, outgoing.z).normalize();
+
+        let length_squared = v_h.x * v_h.x + v_h.y * v_h.y;
+        let t_1 = if length_squared > 0. {
+            Vec3::new(-v_h.y, v_h.x, 0.) / length_squared.sqrt()
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let t_2 = v_h.cross(&t_1);
+
+        // Sample the projected area of the hemisphere
+        let r = rng.gen::<f64>().sqrt();
+        let phi = 2. * PI * rng.gen::<f64>();
+        let p_1 = r * phi.cos();
+        let s = 0.5 * (1. + v_h.z);
+        let p_2 = (1. - s) * (1. - p_1 * p_1).sqrt() + s * r * phi.sin();
+        let n_h = p_1 * t_1 + p_2 * t_2 + (1. - p_1 * p_1 - p_2 * p_2).max(0.).sqrt() * v_h;
+
+        // Unstretch back to the ellipsoid configuration
+        return Vec3::new(alpha * n_h.x, alpha * n_h.y, n_h.z.max(0.)).normalize();
+    }
 }
 
 impl Material for Metal {
-    fn scatter(
-        &self,
-        mut _rng: &mut rand::rngs::ThreadRng,
-        incoming_ray: &Ray,
-        rec: &HitRecord,
-    ) -> Option<(Color, Ray)> {
-        let reflected = incoming_ray.direction.reflect(&rec.normal);
-        let scattered_ray = Ray::new(rec.p, reflected);
-        let attenuation = self.albedo;
-        return Some((attenuation, scattered_ray));
-    }
-}
-
-// TODO(geoff): Add dielectric material
-
-pub static MATERIAL_CONCRETE: Lambertian = Lambertian {
-    albedo: Color::new_const(0.5, 0.5, 0.5),
-};
-pub static MATERIAL_GROUND: Lambertian = Lambertian {
-    albedo: Color::new_const(0.8, 0.8, 0.),
-};
-pub static MATERIAL_COPPER: Metal = Metal {
-    albedo: Color::new_const(0.7, 0.5, 0.3),
-};
-pub static MATERIAL_SILVER: Metal = Metal {
-    albedo: Color::new_const(0.9, 0.9, 0.9),
-};
-pub static MATERIAL_RED_PLASTIC: Lambertian = Lambertian {
-    albedo: Color::new_const(0.9, 0.1, 0.1),
-};
-
-pub static MATERIAL_GLASS: Dielectric = Dielectric {
-    refraction_index: 1.5,
-};
-
-pub static MATERIALS: [&'static dyn Material; 6] = [
-    // Hand defined
-    &MATERIAL_CONCRETE,
-    &MATERIAL_GROUND,
-    &MATERIAL_COPPER,
-    &MATERIAL_SILVER,
-    &MATERIAL_RED_PLASTIC,
-    &MATERIAL_GLASS,
-];
-
-pub fn random_material_from_presets(rng: &mut rand::rngs::ThreadRng) -> &'static dyn Material {
+    fn sample(&self, rng: &mut SampleRng, rec: &HitRecord, wo: &Vec3) -> Option<BsdfSample> {
+        let albedo = self.albedo.value(rec.u, rec.v, &rec.p);
+        if self.is_specular() {
+            let reflected = (-*wo).reflect(&rec.normal);
+            if reflected.dot(&rec.normal) <= 0. {
+                return None;
+            }
+            return Some(BsdfSample {
+                direction: reflected,
+                weight: albedo,
+                pdf: 0.,
+            });
+        }
+
+        let alpha = self.roughness * self.roughness;
+        let frame = Onb::new(rec.normal);
+        let outgoing = frame.to_local(*wo);
+        if outgoing.z <= 0. {
+            return None;
+        }
+
+        let microfacet_normal = Self::sample_visible_normal(rng, alpha, outgoing);
+        let scattered = (-outgoing).reflect(&microfacet_normal);
+        // Rays scattered below the surface are absorbed
+        if scattered.z <= 0. {
+            return None;
+        }
+
+        // With visible normal sampling the weight reduces to the ratio of the masking-shadowing
+        // term over the masking term, so no energy is created
+        let lambda_outgoing = Self::smith_lambda(alpha, outgoing);
+        let lambda_scattered = Self::smith_lambda(alpha, scattered);
+        let weight = (1. + lambda_outgoing) / (1. + lambda_outgoing + lambda_scattered);
+
+        return Some(BsdfSample {
+            direction: frame.to_world(scattered),
+            weight: weight * albedo,
+            pdf: Self::reflection_pdf(alpha, outgoing, scattered),
+        });
+    }
+
+    // D G2 / (4 cos_o cos_i), times cos_i
+    fn eval(&self, rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> Color {
+        if self.is_specular() {
+            return Color::black();
+        }
+        let alpha = self.roughness * self.roughness;
+        let frame = Onb::new(rec.normal);
+        let (outgoing, scattered) = (frame.to_local(*wo), frame.to_local(*wi));
+        if outgoing.z <= 0. || scattered.z <= 0. {
+            return Color::black();
+        }
+        let half_vector = (outgoing + scattered).normalize();
+        let masking_shadowing =
+            1. / (1. + Self::smith_lambda(alpha, outgoing) + Self::smith_lambda(alpha, scattered));
+        let value = Self::distribution(alpha, half_vector) * masking_shadowing / (4. * outgoing.z);
+        return value * self.albedo.value(rec.u, rec.v, &rec.p);
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> f64 {
+        if self.is_specular() {
+            return 0.;
+        }
+        let alpha = self.roughness * self.roughness;
+        let frame = Onb::new(rec.normal);
+        let (outgoing, scattered) = (frame.to_local(*wo), frame.to_local(*wi));
+        if outgoing.z <= 0. || scattered.z <= 0. {
+            return 0.;
+        }
+        return Self::reflection_pdf(alpha, outgoing, scattered);
+    }
+
+    fn is_specular(&self) -> bool {
+        self.roughness <= 0.
+    }
+}
+
+// Phase function of participating media scattering the same way in every direction
+pub struct Isotropic {
+    pub albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Self {
+        Isotropic::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Self {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn sample(&self, rng: &mut SampleRng, rec: &HitRecord, _wo: &Vec3) -> Option<BsdfSample> {
+        return Some(BsdfSample {
+            direction: Vec3::random_unit_vector(rng),
+            weight: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: 1. / (4. * PI),
+        });
+    }
+
+    fn eval(&self, rec: &HitRecord, _wi: &Vec3, _wo: &Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p) / (4. * PI)
+    }
+
+    fn pdf(&self, _rec: &HitRecord, _wi: &Vec3, _wo: &Vec3) -> f64 {
+        1. / (4. * PI)
+    }
+}
+
+// Henyey-Greenstein phase function. Positive anisotropies scatter forward, like haze, and
+// negative ones backward. An anisotropy of 0 is isotropic.
+pub struct HenyeyGreenstein {
+    pub albedo: Arc<dyn Texture>,
+    pub anisotropy: f64,
+}
+
+impl HenyeyGreenstein {
+    // Anisotropy is expected in ]-1, 1[
+    pub fn new(albedo: Color, anisotropy: f64) -> Self {
+        HenyeyGreenstein::textured(Arc::new(SolidColor::new(albedo)), anisotropy)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, anisotropy: f64) -> Self {
+        HenyeyGreenstein { albedo, anisotropy }
+    }
+
+    // Cosine of the angle between the incoming and scattered directions
+    fn sample_cos_theta(&self, rng: &mut SampleRng) -> f64 {
+        let g = self.anisotropy;
+        let xi = rng.gen::<f64>();
+        if g.abs() < 1e-3 {
+            return 1. - 2. * xi;
+        }
+        let ratio = (1. - g * g) / (1. - g + 2. * g * xi);
+        return ((1. + g * g - ratio * ratio) / (2. * g)).clamp(-1., 1.);
+    }
+
+    fn phase(&self, cos_theta: f64) -> f64 {
+        let g = self.anisotropy;
+        let denominator = 1. + g * g - 2. * g * cos_theta;
+        return (1. - g * g) / (4. * PI * denominator * denominator.sqrt());
+    }
+}
+
+// The scattering angle is the one between the direction of travel, -wo, and the scattered
+// direction
+impl Material for HenyeyGreenstein {
+    fn sample(&self, rng: &mut SampleRng, rec: &HitRecord, wo: &Vec3) -> Option<BsdfSample> {
+        // The phase function is sampled exactly, so the weight is just the albedo
+        let cos_theta = self.sample_cos_theta(rng);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f64>();
+        let frame = Onb::new(-*wo);
+        let direction = frame.to_world(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        return Some(BsdfSample {
+            direction,
+            weight: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: self.phase(cos_theta),
+        });
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> Color {
+        self.phase(-wo.dot(wi)) * self.albedo.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn pdf(&self, _rec: &HitRecord, wi: &Vec3, wo: &Vec3) -> f64 {
+        self.phase(-wo.dot(wi))
+    }
+}
+
+// Light source emitting uniformly from the front face of its surface, it does not scatter
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+
+impl DiffuseLight {
+    pub const fn new(emit: Color) -> Self {
+        DiffuseLight { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _rng: &mut SampleRng, _rec: &HitRecord, _wo: &Vec3) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, _incoming_ray: &Ray, rec: &HitRecord) -> Color {
+        if !rec.front_face {
+            return Color::black();
+        }
+        return self.emit;
+    }
+}
+
+lazy_static! {
+    pub static ref MATERIAL_CONCRETE: Arc<Lambertian> =
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    pub static ref MATERIAL_GROUND: Arc<Lambertian> =
+        Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.)));
+    pub static ref MATERIAL_COPPER: Arc<Metal> = Arc::new(Metal::new(Color::new(0.7, 0.5, 0.3)));
+    pub static ref MATERIAL_SILVER: Arc<Metal> = Arc::new(Metal::new(Color::new(0.9, 0.9, 0.9)));
+    pub static ref MATERIAL_BRUSHED_COPPER: Arc<Metal> =
+        Arc::new(Metal::with_roughness(Color::new(0.7, 0.5, 0.3), 0.35));
+    pub static ref MATERIAL_BRUSHED_SILVER: Arc<Metal> =
+        Arc::new(Metal::with_roughness(Color::new(0.9, 0.9, 0.9), 0.35));
+    pub static ref MATERIAL_RED_PLASTIC: Arc<Lambertian> =
+        Arc::new(Lambertian::new(Color::new(0.9, 0.1, 0.1)));
+    pub static ref MATERIAL_GLASS: Arc<Dielectric> = Arc::new(Dielectric::new(1.5));
+
+    pub static ref MATERIALS: [Arc<dyn Material>; 8] = [
+        // Hand defined
+        MATERIAL_CONCRETE.clone(),
+        MATERIAL_GROUND.clone(),
+        MATERIAL_COPPER.clone(),
+        MATERIAL_SILVER.clone(),
+        MATERIAL_BRUSHED_COPPER.clone(),
+        MATERIAL_BRUSHED_SILVER.clone(),
+        MATERIAL_RED_PLASTIC.clone(),
+        MATERIAL_GLASS.clone(),
+    ];
+}
+
+pub fn random_material_from_presets(rng: &mut SampleRng) -> Arc<dyn Material> {
     let material_index = rng.gen_range(0..MATERIALS.len());
-    MATERIALS[material_index]
+    MATERIALS[material_index].clone()
 }
 
-fn random_material_type(rng: &mut rand::rngs::ThreadRng) -> MaterialType {
+fn random_material_type(rng: &mut SampleRng) -> MaterialType {
     let material_type_index = rng.gen_range(0..2);
     match material_type_index {
         0 => MaterialType::Lambertian,
@@ -179,17 +525,226 @@ fn random_material_type(rng: &mut rand::rngs::ThreadRng) -> MaterialType {
     }
 }
 
-pub fn random_static_material(rng: &mut rand::rngs::ThreadRng) -> &'static dyn Material {
+pub fn random_material(rng: &mut SampleRng) -> Arc<dyn Material> {
     let material_type = random_material_type(rng);
     match material_type {
-        MaterialType::Lambertian => Box::leak(Box::new(Lambertian {
-            albedo: Color::random(rng),
-        })),
-        MaterialType::Metal => Box::leak(Box::new(Metal {
-            albedo: Color::random(rng),
-        })),
-        MaterialType::Dielectric => Box::leak(Box::new(Dielectric {
-            refraction_index: rng.gen_range(1.0..2.0),
-        })),
+        MaterialType::Lambertian => Arc::new(Lambertian::new(Color::random(rng))),
+        MaterialType::Metal => Arc::new(Metal::with_roughness(
+            Color::random(rng),
+            rng.gen_range(0.0..0.5),
+        )),
+        MaterialType::Dielectric => Arc::new(Dielectric::new(rng.gen_range(1.0..2.0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Point3;
+    use rand::SeedableRng;
+
+    fn glass_surface_hit() -> HitRecord<'static> {
+        HitRecord {
+            p: Point3::zeros(),
+            normal: Vec3::new(0., 1., 0.),
+            front_face: true,
+            ..Default::default()
+        }
+    }
+
+    // Fraction of rays reflected off a glass surface hit from above at the given angle
+    fn reflection_ratio(glass: &Dielectric, cos_theta: f64, n_rays: usize) -> f64 {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = glass_surface_hit();
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let incoming_ray = Ray::new(
+            Point3::new(0., 1., 0.),
+            Vec3::new(sin_theta, -cos_theta, 0.),
+        );
+
+        let mut n_reflected = 0;
+        for _ in 0..n_rays {
+            let (attenuation, scattered_ray) =
+                glass.scatter(&mut rng, &incoming_ray, &rec).unwrap();
+            assert_eq!(attenuation, Color::white());
+            if scattered_ray.direction.dot(&rec.normal) > 0. {
+                n_reflected += 1;
+            }
+        }
+        return n_reflected as f64 / n_rays as f64;
+    }
+
+    #[test]
+    fn test_sample_weights_are_eval_over_pdf() {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = glass_surface_hit();
+        let wo = Vec3::new(0.6, 0.8, 0.);
+        let materials: [Arc<dyn Material>; 4] = [
+            Arc::new(Lambertian::new(Color::new(0.2, 0.4, 0.6))),
+            Arc::new(Metal::with_roughness(Color::new(0.9, 0.5, 0.2), 0.4)),
+            Arc::new(Isotropic::new(Color::white())),
+            Arc::new(HenyeyGreenstein::new(Color::white(), 0.6)),
+        ];
+        for material in materials {
+            assert!(!material.is_specular());
+            for _ in 0..1000 {
+                let Some(sample) = material.sample(&mut rng, &rec, &wo) else {
+                    continue;
+                };
+                let pdf = material.pdf(&rec, &sample.direction, &wo);
+                assert!((pdf - sample.pdf).abs() <= 1e-9 * pdf);
+                let weight = material.eval(&rec, &sample.direction, &wo) / pdf;
+                assert!(weight.close_to_with_tol(sample.weight, 1e-9));
+            }
+        }
+
+        assert!(MATERIAL_GLASS.is_specular());
+        assert!(MATERIAL_COPPER.is_specular());
+        assert!(!MATERIAL_BRUSHED_COPPER.is_specular());
+        assert_eq!(
+            MATERIAL_COPPER.pdf(&rec, &Vec3::new(-0.6, 0.8, 0.), &wo),
+            0.
+        );
+    }
+
+    #[test]
+    fn test_pdfs_integrate_to_one() {
+        // Uniform directions over the sphere, the densities of directions below the surface
+        // are 0
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = glass_surface_hit();
+        let wo = Vec3::new(0., 1., 0.);
+        let n_directions = 200_000;
+        let lambertian = Lambertian::new(Color::white());
+        let metal = Metal::with_roughness(Color::white(), 0.6);
+        let (mut total_lambertian, mut total_metal) = (0., 0.);
+        for _ in 0..n_directions {
+            let wi = Vec3::random_unit_vector(&mut rng);
+            total_lambertian += lambertian.pdf(&rec, &wi, &wo);
+            total_metal += metal.pdf(&rec, &wi, &wo);
+        }
+        let integral = |total: f64| 4. * PI * total / n_directions as f64;
+        assert!((integral(total_lambertian) - 1.).abs() < 0.01);
+        // Microfacets tilted by more than 45 degrees reflect below the surface, the GGX
+        // distribution has alpha^2 / (alpha^2 + 1) of them
+        let alpha_squared = 0.6f64.powi(4);
+        let expected = 1. - alpha_squared / (alpha_squared + 1.);
+        assert!((integral(total_metal) - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_smooth_metal_is_a_mirror() {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = glass_surface_hit();
+        let incoming_ray = Ray::new(Point3::new(-1., 1., 0.), Vec3::new(1., -1., 0.));
+        let (attenuation, scattered_ray) = MATERIAL_COPPER
+            .scatter(&mut rng, &incoming_ray, &rec)
+            .unwrap();
+        assert_eq!(attenuation, Color::new(0.7, 0.5, 0.3));
+        assert!(scattered_ray
+            .direction
+            .close_to_with_tol(Vec3::new(1., 1., 0.).normalize(), 1e-12));
+    }
+
+    #[test]
+    fn test_rough_metal_scatters_above_surface_without_gaining_energy() {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = glass_surface_hit();
+        let metal = Metal::with_roughness(Color::white(), 0.6);
+        for cos_theta in [1f64, 0.5, 0.05] {
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            let incoming_ray = Ray::new(
+                Point3::new(0., 1., 0.),
+                Vec3::new(sin_theta, -cos_theta, 0.),
+            );
+
+            let n_rays = 10_000;
+            let mut total_weight = 0.;
+            for _ in 0..n_rays {
+                if let Some((attenuation, scattered_ray)) =
+                    metal.scatter(&mut rng, &incoming_ray, &rec)
+                {
+                    assert!(scattered_ray.direction.dot(&rec.normal) > 0.);
+                    assert!(attenuation.x <= 1. && attenuation.x > 0.);
+                    total_weight += attenuation.x;
+                }
+            }
+            let albedo = total_weight / n_rays as f64;
+            // Single scattering loses some energy, more at grazing angles
+            assert!(albedo <= 1. && albedo > 0.5, "albedo {}", albedo);
+        }
+    }
+
+    #[test]
+    fn test_henyey_greenstein_mean_cosine() {
+        // The anisotropy is the average cosine of the scattering angle
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = glass_surface_hit();
+        let incoming_ray = Ray::new(Point3::new(0., 0., 1.), Vec3::new(0., 0., -2.));
+        let n_rays = 20_000;
+        for anisotropy in [-0.7, 0., 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(Color::white(), anisotropy);
+            let mut total_cos = 0.;
+            for _ in 0..n_rays {
+                let (attenuation, scattered_ray) =
+                    phase.scatter(&mut rng, &incoming_ray, &rec).unwrap();
+                assert_eq!(attenuation, Color::white());
+                let direction = scattered_ray.direction;
+                assert!((direction.length() - 1.).abs() < 1e-9);
+                total_cos += -direction.z;
+            }
+            let mean_cos = total_cos / n_rays as f64;
+            assert!(
+                (mean_cos - anisotropy).abs() < 0.02,
+                "anisotropy {}: mean cosine {}",
+                anisotropy,
+                mean_cos
+            );
+        }
+    }
+
+    #[test]
+    fn test_reflectance_at_normal_incidence() {
+        // ((1 - 1.5) / (1 + 1.5))^2 for both models
+        for model in [FresnelModel::Schlick, FresnelModel::Exact] {
+            assert!((model.reflectance(1., 1. / 1.5) - 0.04).abs() < 1e-12);
+            assert!((model.reflectance(0., 1. / 1.5) - 1.).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_exact_reflectance() {
+        // At Brewster's angle only the s-polarized light is reflected
+        let brewster = (1.5f64).atan();
+        let cos_theta_t = (1. - (brewster.sin() / 1.5).powi(2)).sqrt();
+        let r_s =
+            ((brewster.cos() - 1.5 * cos_theta_t) / (brewster.cos() + 1.5 * cos_theta_t)).powi(2);
+        let reflectance = FresnelModel::Exact.reflectance(brewster.cos(), 1. / 1.5);
+        assert!((reflectance - 0.5 * r_s).abs() < 1e-12);
+
+        // Total internal reflection from inside the glass
+        assert_eq!(FresnelModel::Exact.reflectance(0.5, 1.5), 1.);
+    }
+
+    #[test]
+    fn test_reflection_ratio_at_grazing_angles() {
+        let n_rays = 20_000;
+        for fresnel in [FresnelModel::Schlick, FresnelModel::Exact] {
+            let glass = Dielectric::with_fresnel(1.5, fresnel);
+            for cos_theta in [0.05, 0.2, 0.5] {
+                let expected = fresnel.reflectance(cos_theta, 1. / 1.5);
+                let ratio = reflection_ratio(&glass, cos_theta, n_rays);
+                // Five standard deviations of a binomial proportion
+                let tolerance = 5. * (expected * (1. - expected) / n_rays as f64).sqrt();
+                assert!(
+                    (ratio - expected).abs() < tolerance,
+                    "{:?} at cos_theta = {}: reflected {} of the rays, expected {}",
+                    fresnel,
+                    cos_theta,
+                    ratio,
+                    expected
+                );
+            }
+        }
     }
 }
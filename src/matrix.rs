@@ -0,0 +1,203 @@
+use crate::utils;
+use crate::vec::{Point3, Vec3};
+
+use std::ops::Mul;
+
+// 4x4 matrix acting on homogeneous coordinates, row major
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Matrix4 {
+    pub const fn identity() -> Matrix4 {
+        Matrix4 {
+            m: [
+                [1., 0., 0., 0.],
+                [0., 1., 0., 0.],
+                [0., 0., 1., 0.],
+                [0., 0., 0., 1.],
+            ],
+        }
+    }
+
+    pub fn translation(offset: Vec3) -> Matrix4 {
+        let mut matrix = Self::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        return matrix;
+    }
+
+    pub fn scaling(factors: Vec3) -> Matrix4 {
+        let mut matrix = Self::identity();
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        return matrix;
+    }
+
+    // Counterclockwise rotation around `axis` when looking against it, angle in degrees
+    pub fn rotation(axis: Vec3, degrees: f64) -> Matrix4 {
+        let axis = axis.normalize();
+        let (sin, cos) = utils::degrees_to_radians(degrees).sin_cos();
+        let (x, y, z) = (axis.x, axis.y, axis.z);
+        let c = 1. - cos;
+        Matrix4 {
+            m: [
+                [
+                    cos + x * x * c,
+                    x * y * c - z * sin,
+                    x * z * c + y * sin,
+                    0.,
+                ],
+                [
+                    y * x * c + z * sin,
+                    cos + y * y * c,
+                    y * z * c - x * sin,
+                    0.,
+                ],
+                [
+                    z * x * c - y * sin,
+                    z * y * c + x * sin,
+                    cos + z * z * c,
+                    0.,
+                ],
+                [0., 0., 0., 1.],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut transposed = Self::identity();
+        for (i, row) in self.m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                transposed.m[j][i] = *value;
+            }
+        }
+        return transposed;
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None for singular matrices
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inverse = Self::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1. / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for i in 0..4 {
+                if i == column {
+                    continue;
+                }
+                let factor = a[i][column];
+                for j in 0..4 {
+                    a[i][j] -= factor * a[column][j];
+                    inverse[i][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+        return Some(Matrix4 { m: inverse });
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1. {
+            return Point3::new(x, y, z);
+        }
+        return Point3::new(x, y, z) / w;
+    }
+
+    // Directions ignore the translation
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    // Applying the product is applying `rhs` first, then `self`
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        let mut product = [[0.; 4]; 4];
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        return Matrix4 { m: product };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-12, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_transforms() {
+        let p = Point3::new(1., 2., 3.);
+        let translation = Matrix4::translation(Vec3::new(1., -1., 0.5));
+        assert_close(translation.transform_point(&p), Point3::new(2., 1., 3.5));
+        assert_close(translation.transform_vector(&p), p);
+
+        let rotation = Matrix4::rotation(Vec3::new(0., 0., 1.), 90.);
+        assert_close(
+            rotation.transform_vector(&Vec3::new(1., 0., 0.)),
+            Vec3::new(0., 1., 0.),
+        );
+
+        // Scale, then rotate, then translate
+        let transform = translation * rotation * Matrix4::scaling(Vec3::new(2., 2., 2.));
+        assert_close(
+            transform.transform_point(&Point3::new(1., 0., 0.)),
+            Point3::new(1., 1., 0.5),
+        );
+    }
+
+    #[test]
+    fn test_inverse() {
+        let transform = Matrix4::translation(Vec3::new(3., -2., 1.))
+            * Matrix4::rotation(Vec3::new(1., 1., 0.), 33.)
+            * Matrix4::scaling(Vec3::new(0.5, 2., 3.));
+        let inverse = transform.inverse().unwrap();
+        let product = transform * inverse;
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((product.m[i][j] - expected).abs() < 1e-12);
+            }
+        }
+        assert_eq!(transform.transpose().transpose(), transform);
+        assert!(Matrix4::scaling(Vec3::new(1., 0., 1.)).inverse().is_none());
+    }
+}
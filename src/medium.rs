@@ -0,0 +1,184 @@
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::{Isotropic, Material};
+use crate::ray::Ray;
+use crate::rng::{hash_values, ray_rng};
+use crate::vec::Vec3;
+
+use rand::Rng;
+use std::sync::Arc;
+
+// Participating medium of constant density filling a closed boundary, like fog or smoke.
+// Rays travelling through it scatter after an exponentially distributed distance, and the
+// phase function material decides where they go. The boundary is expected to be convex:
+// rays are assumed to enter and leave it only once.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+    // Tells apart the media crossed by the same ray, so that they scatter independently
+    salt: u64,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable>, density: f64, albedo: Color) -> Self {
+        ConstantMedium::with_phase_function(boundary, density, Arc::new(Isotropic::new(albedo)))
+    }
+
+    pub fn with_phase_function(
+        boundary: Box<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> Self {
+        let bbox = boundary.bounding_box();
+        let salt = hash_values(&[
+            bbox.x.lower,
+            bbox.x.upper,
+            bbox.y.lower,
+            bbox.y.upper,
+            bbox.z.lower,
+            bbox.z.upper,
+            density,
+        ]);
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase_function,
+            salt,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        // Where the whole line enters and leaves the boundary
+        let entry = self.boundary.hit(ray, Interval::default())?;
+        let exit = self
+            .boundary
+            .hit(ray, Interval::new(entry.t + 0.0001, f64::INFINITY))?;
+
+        let t_entry = entry.t.max(ray_t.lower).max(0.);
+        let t_exit = exit.t.min(ray_t.upper);
+        if t_entry >= t_exit {
+            return None;
+        }
+
+        // Sampled from the ray itself, so that the BVH testing the medium again with a
+        // shorter interval finds the same scattering point
+        let ray_length = ray.direction.length();
+        let distance_inside = (t_exit - t_entry) * ray_length;
+        let hit_distance = self.neg_inv_density * (1. - ray_rng(ray, self.salt).gen::<f64>()).ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = t_entry + hit_distance / ray_length;
+        return Some(HitRecord {
+            t,
+            p: ray.at(t),
+            // Arbitrary, there is no surface
+            normal: Vec3::new(1., 0., 0.),
+            front_face: true,
+            material: &*self.phase_function,
+            ..Default::default()
+        });
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::material::MATERIAL_CONCRETE;
+    use crate::sphere::Sphere;
+    use crate::vec::Point3;
+
+    fn fog_at(center: Point3, density: f64) -> ConstantMedium {
+        let boundary = Sphere::new(center, 1., MATERIAL_CONCRETE.clone());
+        ConstantMedium::new(Box::new(boundary), density, Color::white())
+    }
+
+    fn fog(density: f64) -> ConstantMedium {
+        fog_at(Point3::zeros(), density)
+    }
+
+    // Fraction of parallel rays going down the z axis through the media
+    fn transmittance(medium: &dyn Hittable, n_rays: usize) -> f64 {
+        let mut n_transmitted = 0;
+        for i in 0..n_rays {
+            // Tiny offsets so every ray is different
+            let origin = Point3::new(1e-9 * i as f64, 0., 5.);
+            let ray = Ray::new(origin, Vec3::new(0., 0., -1.));
+            if medium
+                .hit(&ray, Interval::new(0.001, f64::INFINITY))
+                .is_none()
+            {
+                n_transmitted += 1;
+            }
+        }
+        return n_transmitted as f64 / n_rays as f64;
+    }
+
+    #[test]
+    fn test_beer_lambert_transmittance() {
+        let n_rays = 20_000;
+        for density in [0.1f64, 0.5, 2.] {
+            // Two units of medium along the diameter
+            let expected = (-2. * density).exp();
+            let ratio = transmittance(&fog(density), n_rays);
+            let tolerance = 5. * (expected * (1. - expected) / n_rays as f64).sqrt();
+            assert!(
+                (ratio - expected).abs() < tolerance,
+                "density {}: transmitted {}, expected {}",
+                density,
+                ratio,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_media_in_a_row_multiply_their_transmittance() {
+        let n_rays = 20_000;
+        let mut media = HittableList::new();
+        media.add(Box::new(fog_at(Point3::new(0., 0., 0.), 0.3)));
+        media.add(Box::new(fog_at(Point3::new(0., 0., -3.), 0.3)));
+        // Two units of medium in each sphere
+        let expected = (-2. * 0.3f64).exp().powi(2);
+        let ratio = transmittance(&media, n_rays);
+        let tolerance = 5. * (expected * (1. - expected) / n_rays as f64).sqrt();
+        assert!(
+            (ratio - expected).abs() < tolerance,
+            "transmitted {}, expected {}",
+            ratio,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_scattering_inside_the_boundary() {
+        let medium = fog(100.);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let ray = Ray::new(Point3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let record = medium.hit(&ray, ray_t).unwrap();
+        assert!(record.t > 4. && record.t < 6.);
+        // Same answer when a BVH asks again with a closer bound
+        let again = medium
+            .hit(&ray, Interval::new(0.001, record.t + 0.5))
+            .unwrap();
+        assert_eq!(record.t, again.t);
+        // Nothing past the point where the ray stops
+        assert!(medium.hit(&ray, Interval::new(0.001, 3.)).is_none());
+
+        // Rays starting inside the medium scatter too
+        let inside = Ray::new(Point3::zeros(), Vec3::new(0., 1., 0.));
+        let record = medium.hit(&inside, ray_t).unwrap();
+        assert!(record.t > 0. && record.t < 1.);
+    }
+}
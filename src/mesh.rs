@@ -0,0 +1,234 @@
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::triangle::{intersect_triangle, BBOX_PADDING};
+use crate::vec::{Point3, Vec3};
+
+use std::sync::Arc;
+
+// Indexed vertex buffers, shared by every mesh built from them. Normals and uvs are either
+// empty or given for each vertex.
+#[derive(Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    pub fn validate(&self) -> Result<(), String> {
+        let n_vertices = self.positions.len();
+        if !self.normals.is_empty() && self.normals.len() != n_vertices {
+            return Err(format!(
+                "{} normals for {} vertices",
+                self.normals.len(),
+                n_vertices
+            ));
+        }
+        if !self.uvs.is_empty() && self.uvs.len() != n_vertices {
+            return Err(format!(
+                "{} uvs for {} vertices",
+                self.uvs.len(),
+                n_vertices
+            ));
+        }
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            if let Some(index) = triangle.iter().find(|&&index| index >= n_vertices) {
+                return Err(format!(
+                    "triangle {} uses vertex {} but there are only {} vertices",
+                    i, index, n_vertices
+                ));
+            }
+        }
+        return Ok(());
+    }
+}
+
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    index: usize,
+    material: Arc<dyn Material>,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mesh = &self.mesh;
+        let [i0, i1, i2] = mesh.triangles[self.index];
+        let (p0, p1, p2) = (mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]);
+        let (t, b1, b2) = intersect_triangle(ray, ray_t, p0, p1, p2)?;
+        let b0 = 1. - b1 - b2;
+
+        let (u, v) = if mesh.uvs.is_empty() {
+            (b1, b2)
+        } else {
+            let (uv0, uv1, uv2) = (mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]);
+            (
+                b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            )
+        };
+
+        let mut record = HitRecord {
+            t,
+            p: ray.at(t),
+            u,
+            v,
+            material: &*self.material,
+            ..Default::default()
+        };
+        // The geometric normal decides which side was hit
+        let geometric_normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+        record.set_face_normal(ray, geometric_normal);
+
+        if !mesh.normals.is_empty() {
+            let mut shading_normal =
+                (b0 * mesh.normals[i0] + b1 * mesh.normals[i1] + b2 * mesh.normals[i2]).normalize();
+            if shading_normal.dot(&geometric_normal) < 0. {
+                shading_normal = -shading_normal;
+            }
+            record.normal = if record.front_face {
+                shading_normal
+            } else {
+                -shading_normal
+            };
+        }
+        return Some(record);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [i0, i1, i2] = self.mesh.triangles[self.index];
+        let positions = &self.mesh.positions;
+        Aabb::enclosing(
+            &Aabb::from_points(positions[i0], positions[i1]),
+            &Aabb::from_points(positions[i0], positions[i2]),
+        )
+        .pad_to_minimums(BBOX_PADDING)
+    }
+}
+
+// Triangle mesh with smooth shading when the mesh has normals, with its own BVH
+pub struct TriangleMesh {
+    pub mesh: Arc<MeshData>,
+    bvh: BvhNode,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Arc<MeshData>, material: Arc<dyn Material>) -> Result<Self, String> {
+        mesh.validate()
+            .map_err(|message| format!("invalid mesh: {}", message))?;
+        let mut triangles = HittableList::new();
+        for index in 0..mesh.triangles.len() {
+            triangles.add(Box::new(MeshTriangle {
+                mesh: mesh.clone(),
+                index,
+                material: material.clone(),
+            }));
+        }
+        Ok(Self {
+            mesh,
+            bvh: BvhNode::new(triangles),
+        })
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        self.bvh.hit(ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MATERIAL_CONCRETE;
+
+    // Unit square in the z = 0 plane, made of two triangles
+    fn square() -> MeshData {
+        MeshData {
+            positions: vec![
+                Point3::new(0., 0., 0.),
+                Point3::new(1., 0., 0.),
+                Point3::new(1., 1., 0.),
+                Point3::new(0., 1., 0.),
+            ],
+            normals: Vec::new(),
+            uvs: vec![(0., 0.), (1., 0.), (1., 1.), (0., 1.)],
+            triangles: vec![[0, 1, 2], [0, 2, 3]],
+        }
+    }
+
+    fn shoot(mesh: &TriangleMesh, x: f64, y: f64) -> Option<HitRecord<'_>> {
+        let ray = Ray::new(Point3::new(x, y, 1.), Vec3::new(0., 0., -1.));
+        mesh.hit(&ray, Interval::new(0.001, f64::INFINITY))
+    }
+
+    #[test]
+    fn test_uvs_are_interpolated() {
+        let mesh = TriangleMesh::new(Arc::new(square()), MATERIAL_CONCRETE.clone()).unwrap();
+        for (x, y) in [(0.2, 0.7), (0.8, 0.1), (0.5, 0.5)] {
+            let record = shoot(&mesh, x, y).unwrap();
+            assert!((record.u - x).abs() < 1e-12 && (record.v - y).abs() < 1e-12);
+            assert_eq!(record.normal, Vec3::new(0., 0., 1.));
+        }
+        assert!(shoot(&mesh, 1.2, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_smooth_normals_are_interpolated() {
+        let mut data = square();
+        // Normals tilted outwards along x, as on a cylinder
+        data.normals = vec![
+            Vec3::new(-1., 0., 1.).normalize(),
+            Vec3::new(1., 0., 1.).normalize(),
+            Vec3::new(1., 0., 1.).normalize(),
+            Vec3::new(-1., 0., 1.).normalize(),
+        ];
+        let mesh = TriangleMesh::new(Arc::new(data), MATERIAL_CONCRETE.clone()).unwrap();
+
+        let center = shoot(&mesh, 0.5, 0.5).unwrap();
+        assert!(center
+            .normal
+            .close_to_with_tol(Vec3::new(0., 0., 1.), 1e-12));
+        let right = shoot(&mesh, 0.9, 0.3).unwrap();
+        assert!(right.normal.x > 0. && (right.normal.length() - 1.).abs() < 1e-12);
+
+        // Hit from below, the normal faces the ray
+        let ray = Ray::new(Point3::new(0.9, 0.3, -1.), Vec3::new(0., 0., 1.));
+        let below = mesh.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert!(!below.front_face);
+        assert!(below.normal.z < 0. && below.normal.x < 0.);
+    }
+
+    #[test]
+    fn test_meshes_share_vertex_buffers() {
+        let data = Arc::new(square());
+        let a = TriangleMesh::new(data.clone(), MATERIAL_CONCRETE.clone()).unwrap();
+        let b = TriangleMesh::new(data.clone(), MATERIAL_CONCRETE.clone()).unwrap();
+        assert!(Arc::ptr_eq(&a.mesh, &b.mesh));
+        assert!(shoot(&b, 0.5, 0.2).is_some());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut data = square();
+        assert!(data.validate().is_ok());
+        data.triangles.push([0, 1, 4]);
+        assert!(data.validate().is_err());
+        let error = TriangleMesh::new(Arc::new(data), MATERIAL_CONCRETE.clone())
+            .err()
+            .unwrap();
+        assert!(error.contains("vertex 4"), "{}", error);
+        let mut data = square();
+        data.normals.push(Vec3::new(0., 0., 1.));
+        assert!(data.validate().is_err());
+    }
+}
@@ -0,0 +1,554 @@
+use crate::color::Color;
+use crate::hittable_list::HittableList;
+use crate::material::{Dielectric, Lambertian, Material, MaterialType, Metal, MATERIAL_CONCRETE};
+use crate::mesh::{MeshData, TriangleMesh};
+use crate::vec::{Point3, Vec3};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct ObjError {
+    pub path: PathBuf,
+    // 1-based line of the error, 0 if the file could not be read
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}", self.path.display(), self.message);
+        }
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|error| ObjError {
+        path: path.to_path_buf(),
+        line: 0,
+        message: error.to_string(),
+    })
+}
+
+// Tokens of one line, reporting errors with their position
+struct Line<'a> {
+    path: &'a Path,
+    number: usize,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Line<'a> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError {
+            path: self.path.to_path_buf(),
+            line: self.number,
+            message,
+        }
+    }
+
+    fn float(&mut self, what: &str) -> Result<f64, ObjError> {
+        let token = self
+            .tokens
+            .next()
+            .ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} `{}`", what, token)))
+    }
+
+    fn floats<const N: usize>(&mut self, what: &str) -> Result<[f64; N], ObjError> {
+        let mut values = [0.; N];
+        for value in &mut values {
+            *value = self.float(what)?;
+        }
+        return Ok(values);
+    }
+
+    fn name(&mut self, what: &str) -> Result<&'a str, ObjError> {
+        self.tokens
+            .next()
+            .ok_or_else(|| self.error(format!("missing {}", what)))
+    }
+}
+
+fn lines<'a>(path: &'a Path, source: &'a str) -> impl Iterator<Item = (&'a str, Line<'a>)> {
+    source.lines().enumerate().filter_map(move |(i, text)| {
+        let text = text.split('#').next().unwrap_or("");
+        let mut tokens = text.split_whitespace();
+        let keyword = tokens.next()?;
+        Some((
+            keyword,
+            Line {
+                path,
+                number: i + 1,
+                tokens,
+            },
+        ))
+    })
+}
+
+// Material properties gathered from a `newmtl` block
+#[derive(Default)]
+struct MtlDesc {
+    diffuse: Option<Color>,
+    specular: Option<Color>,
+    shininess: Option<f64>,
+    refraction_index: Option<f64>,
+    dissolve: Option<f64>,
+    illum: Option<u32>,
+}
+
+impl MtlDesc {
+    // Closest material type we have: transparent materials become glass, the ones with
+    // raytraced reflections become metals and everything else is diffuse
+    fn material_type(&self) -> MaterialType {
+        if self.dissolve.is_some_and(|dissolve| dissolve < 1.)
+            || matches!(self.illum, Some(4 | 6 | 7 | 9))
+        {
+            return MaterialType::Dielectric;
+        }
+        if matches!(self.illum, Some(3 | 5 | 8)) {
+            return MaterialType::Metal;
+        }
+        return MaterialType::Lambertian;
+    }
+
+    // Phong exponent to microfacet roughness. The Beckmann width matching an exponent is
+    // sqrt(2 / (Ns + 2)), and metals square their roughness into that width.
+    fn roughness(&self) -> f64 {
+        self.shininess
+            .map_or(0., |shininess| (2. / (shininess.max(0.) + 2.)).powf(0.25))
+    }
+
+    fn to_material(&self) -> Arc<dyn Material> {
+        let diffuse = self.diffuse.unwrap_or(Color::new(0.8, 0.8, 0.8));
+        match self.material_type() {
+            MaterialType::Lambertian => Arc::new(Lambertian::new(diffuse)),
+            MaterialType::Metal => {
+                let albedo = match self.specular {
+                    Some(specular) if specular.length_squared() > 0. => specular,
+                    _ => diffuse,
+                };
+                Arc::new(Metal::with_roughness(albedo, self.roughness()))
+            }
+            MaterialType::Dielectric => {
+                Arc::new(Dielectric::new(self.refraction_index.unwrap_or(1.5)))
+            }
+        }
+    }
+}
+
+fn parse_mtl_descs(path: &Path, source: &str) -> Result<Vec<(String, MtlDesc)>, ObjError> {
+    let mut descs: Vec<(String, MtlDesc)> = Vec::new();
+    for (keyword, mut line) in lines(path, source) {
+        if keyword == "newmtl" {
+            let name = line.name("material name")?;
+            descs.push((name.to_string(), MtlDesc::default()));
+            continue;
+        }
+        let desc = match descs.last_mut() {
+            Some((_, desc)) => desc,
+            None => {
+                return Err(line.error(format!("`{}` before any `newmtl`", keyword)));
+            }
+        };
+        match keyword {
+            "Kd" => desc.diffuse = Some(Color::from_slice(&line.floats::<3>("color")?)),
+            "Ks" => desc.specular = Some(Color::from_slice(&line.floats::<3>("color")?)),
+            "Ns" => desc.shininess = Some(line.float("shininess")?),
+            "Ni" => desc.refraction_index = Some(line.float("refraction index")?),
+            "d" => desc.dissolve = Some(line.float("dissolve")?),
+            "Tr" => desc.dissolve = Some(1. - line.float("transparency")?),
+            "illum" => {
+                let token = line.name("illumination model")?;
+                let illum = token
+                    .parse()
+                    .map_err(|_| line.error(format!("invalid illumination model `{}`", token)))?;
+                desc.illum = Some(illum);
+            }
+            // Texture maps and the rest are not supported
+            _ => (),
+        }
+    }
+    return Ok(descs);
+}
+
+pub fn parse_mtl(
+    path: &Path,
+    source: &str,
+) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let descs = parse_mtl_descs(path, source)?;
+    return Ok(descs
+        .into_iter()
+        .map(|(name, desc)| (name, desc.to_material()))
+        .collect());
+}
+
+// Triangles sharing a material, with OBJ (position, uv, normal) index triples deduplicated
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    corners: Vec<(usize, Option<usize>, Option<usize>)>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, corner: (usize, Option<usize>, Option<usize>)) -> usize {
+        let next_index = self.corners.len();
+        let index = *self.vertices.entry(corner).or_insert(next_index);
+        if index == next_index {
+            self.corners.push(corner);
+        }
+        return index;
+    }
+
+    fn build(self, positions: &[Point3], uvs: &[(f64, f64)], normals: &[Vec3]) -> MeshData {
+        // Normals and uvs are only kept if every vertex has them
+        let all_uvs = self.corners.iter().all(|corner| corner.1.is_some());
+        let all_normals = self.corners.iter().all(|corner| corner.2.is_some());
+        MeshData {
+            positions: self
+                .corners
+                .iter()
+                .map(|corner| positions[corner.0])
+                .collect(),
+            uvs: if all_uvs {
+                self.corners
+                    .iter()
+                    .map(|corner| uvs[corner.1.unwrap()])
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            normals: if all_normals {
+                self.corners
+                    .iter()
+                    .map(|corner| normals[corner.2.unwrap()])
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            triangles: self.triangles,
+        }
+    }
+}
+
+// Resolve a 1-based or negative (relative to the end) OBJ index
+fn resolve_index(line: &Line, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| line.error(format!("invalid {} index `{}`", what, token)))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(line.error(format!(
+            "{} index {} out of range, {} defined so far",
+            what, index, count
+        )));
+    }
+    return Ok(resolved as usize);
+}
+
+// Parse an OBJ file into one triangle mesh per material, n-gons are fan triangulated. All the
+// faces use `material_override` if it is given, the MTL materials otherwise.
+pub fn parse_obj(
+    path: &Path,
+    source: &str,
+    material_override: Option<Arc<dyn Material>>,
+) -> Result<HittableList, ObjError> {
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    // Meshes in order of first use, keyed by material name
+    let mut builders: Vec<(Option<String>, MeshBuilder)> = Vec::new();
+    let mut current_material: Option<String> = None;
+
+    for (keyword, mut line) in lines(path, source) {
+        match keyword {
+            "v" => positions.push(Point3::from_slice(&line.floats::<3>("vertex coordinate")?)),
+            "vt" => {
+                let u = line.float("texture coordinate")?;
+                // The v coordinate is optional for 1D textures
+                let v = if line.tokens.clone().next().is_some() {
+                    line.float("texture coordinate")?
+                } else {
+                    0.
+                };
+                uvs.push((u, v));
+            }
+            "vn" => {
+                let normal = Vec3::from_slice(&line.floats::<3>("normal coordinate")?);
+                if normal.length_squared() == 0. {
+                    return Err(line.error("zero length normal".to_string()));
+                }
+                normals.push(normal.normalize());
+            }
+            "f" => {
+                let mut corners = Vec::new();
+                for token in line.tokens.clone() {
+                    let parts: Vec<&str> = token.split('/').collect();
+                    if parts.len() > 3 {
+                        return Err(line.error(format!("invalid face vertex `{}`", token)));
+                    }
+                    let position = resolve_index(&line, parts[0], positions.len(), "vertex")?;
+                    let uv = match parts.get(1) {
+                        Some(&"") | None => None,
+                        Some(part) => {
+                            Some(resolve_index(&line, part, uvs.len(), "texture coordinate")?)
+                        }
+                    };
+                    let normal = match parts.get(2) {
+                        Some(&"") | None => None,
+                        Some(part) => Some(resolve_index(&line, part, normals.len(), "normal")?),
+                    };
+                    corners.push((position, uv, normal));
+                }
+                if corners.len() < 3 {
+                    return Err(line.error(format!(
+                        "a face needs at least 3 vertices, got {}",
+                        corners.len()
+                    )));
+                }
+
+                let material_name = current_material.clone();
+                let builder = match builders.iter().position(|(name, _)| *name == material_name) {
+                    Some(i) => &mut builders[i].1,
+                    None => {
+                        builders.push((material_name, MeshBuilder::default()));
+                        &mut builders.last_mut().unwrap().1
+                    }
+                };
+                let indices: Vec<usize> = corners
+                    .into_iter()
+                    .map(|corner| builder.vertex(corner))
+                    .collect();
+                for i in 1..indices.len() - 1 {
+                    builder
+                        .triangles
+                        .push([indices[0], indices[i], indices[i + 1]]);
+                }
+            }
+            "usemtl" => {
+                let name = line.name("material name")?;
+                if material_override.is_none() && !materials.contains_key(name) {
+                    return Err(line.error(format!("unknown material `{}`", name)));
+                }
+                current_material = Some(name.to_string());
+            }
+            "mtllib" => {
+                if material_override.is_some() {
+                    continue;
+                }
+                for library in line.tokens.clone() {
+                    let mtl_path = base_dir.join(library);
+                    let mtl_source = read_file(&mtl_path).map_err(|error| {
+                        line.error(format!("cannot read `{}`: {}", library, error.message))
+                    })?;
+                    materials.extend(parse_mtl(&mtl_path, &mtl_source)?);
+                }
+            }
+            // Groups, smoothing groups, lines and the rest don't change the geometry
+            _ => (),
+        }
+    }
+
+    let mut list = HittableList::new();
+    for (material_name, builder) in builders {
+        let material = match (&material_override, material_name) {
+            (Some(material), _) => material.clone(),
+            (None, Some(name)) => materials[&name].clone(),
+            (None, None) => MATERIAL_CONCRETE.clone(),
+        };
+        let mesh = builder.build(&positions, &uvs, &normals);
+        let mesh = TriangleMesh::new(Arc::new(mesh), material).map_err(|message| ObjError {
+            path: path.to_path_buf(),
+            line: 0,
+            message,
+        })?;
+        list.add(Box::new(mesh));
+    }
+    return Ok(list);
+}
+
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    material_override: Option<Arc<dyn Material>>,
+) -> Result<HittableList, ObjError> {
+    let path = path.as_ref();
+    let source = read_file(path)?;
+    parse_obj(path, &source, material_override)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+
+    fn parse(source: &str) -> Result<HittableList, ObjError> {
+        parse_obj(
+            Path::new("test.obj"),
+            source,
+            Some(MATERIAL_CONCRETE.clone()),
+        )
+    }
+
+    fn parse_error(source: &str) -> ObjError {
+        match parse(source) {
+            Ok(_) => panic!("Expected the OBJ to be rejected"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn test_fan_triangulation() {
+        let source = "
+# A pentagon in the z = 0 plane
+v 0 0 0
+v 2 0 0
+v 3 1 0
+v 1 2 0
+v -1 1 0
+f 1 2 3 4 5
+";
+        let list = parse(source).unwrap();
+        // A single mesh, as all the faces share the same material
+        assert_eq!(list.objects.len(), 1);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        for (x, y) in [(1., 0.5), (2., 1.), (0., 1.), (1., 1.8)] {
+            let ray = Ray::new(Point3::new(x, y, 1.), Vec3::new(0., 0., -1.));
+            assert!(list.hit(&ray, ray_t).is_some(), "missed at {} {}", x, y);
+        }
+        let ray = Ray::new(Point3::new(2.5, 1.8, 1.), Vec3::new(0., 0., -1.));
+        assert!(list.hit(&ray, ray_t).is_none());
+    }
+
+    #[test]
+    fn test_face_index_formats() {
+        let source = "
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 0 1
+vt 0.5
+vn 0 0 2
+f 1/1/1 2/2/1 3/3/1
+f -3//-1 -2//-1 -1//-1
+f 1/1 2/2 3/3
+";
+        let list = parse(source).unwrap();
+        let ray = Ray::new(Point3::new(0.2, 0.3, 1.), Vec3::new(0., 0., -1.));
+        let record = list.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert_eq!(record.normal, Vec3::new(0., 0., 1.));
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let error = parse_error("v 0 0 0\nv 1 0 zero\n");
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("zero"));
+
+        let error = parse_error("v 0 0 0\nv 1 0\n");
+        assert_eq!(error.line, 2);
+
+        let error = parse_error("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n");
+        assert_eq!(error.line, 5);
+        assert!(error.message.contains("out of range"));
+
+        let error = parse_error("v 0 0 0\nv 1 0 0\nf 1 2\n");
+        assert_eq!(error.line, 3);
+
+        let error = parse_error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1/1/1 2 3\n");
+        assert_eq!(error.line, 4);
+        assert_eq!(
+            error.to_string(),
+            "test.obj:4: invalid face vertex `1/1/1/1`"
+        );
+
+        let error = parse_error("v 0 0 0\nvt 0.5 abc\n");
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("abc"));
+
+        let error = parse_error("v 0 0 0\nvn 0 0 0\n");
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("zero length"));
+    }
+
+    #[test]
+    fn test_mtl_material_types() {
+        let source = "
+newmtl plastic
+Kd 0.8 0.1 0.1
+illum 2
+
+newmtl mirror
+Ks 0.9 0.9 0.9
+Ns 200
+illum 3
+
+newmtl brushed
+Kd 0.5 0.5 0.5
+Ns 30
+illum 3
+
+newmtl glass
+Ni 1.45
+d 0.1
+illum 2
+";
+        let path = Path::new("test.mtl");
+        assert_eq!(parse_mtl(path, source).unwrap().len(), 4);
+
+        let descs: HashMap<String, MtlDesc> =
+            parse_mtl_descs(path, source).unwrap().into_iter().collect();
+        assert!(matches!(
+            descs["plastic"].material_type(),
+            MaterialType::Lambertian
+        ));
+        assert!(matches!(
+            descs["mirror"].material_type(),
+            MaterialType::Metal
+        ));
+        assert!(matches!(
+            descs["glass"].material_type(),
+            MaterialType::Dielectric
+        ));
+        assert_eq!(descs["glass"].refraction_index, Some(1.45));
+        // Ns 30 is a Beckmann width of 1/4
+        assert!((descs["brushed"].roughness() - 0.5).abs() < 1e-12);
+        assert_eq!(descs["plastic"].roughness(), 0.);
+
+        let error = parse_mtl_descs(path, "Kd 1 1 1\n").err().unwrap();
+        assert_eq!(error.line, 1);
+        let error = parse_mtl_descs(path, "newmtl a\nillum two\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn test_unknown_material() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl missing\nf 1 2 3\n";
+        let error = parse_obj(Path::new("test.obj"), source, None)
+            .err()
+            .unwrap();
+        assert_eq!(error.line, 4);
+        assert!(error.message.contains("missing"));
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Where a render stands
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    // Over all the passes left when the render started
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    // Camera rays, the rays of their bounces and the shadow rays cast along the way
+    pub rays: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.tiles_total == 0 {
+            return 1.;
+        }
+        return self.tiles_done as f64 / self.tiles_total as f64;
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds <= 0. {
+            return 0.;
+        }
+        return self.rays as f64 / seconds;
+    }
+
+    // Time left if the remaining tiles go as fast as the ones done, unknown before the first
+    pub fn eta(&self) -> Option<Duration> {
+        if self.tiles_done == 0 {
+            return None;
+        }
+        let tiles_left = self.tiles_total.saturating_sub(self.tiles_done);
+        return Some(
+            self.elapsed
+                .mul_f64(tiles_left as f64 / self.tiles_done as f64),
+        );
+    }
+}
+
+// Told about the progress of a render as tiles get done. It is called from the render
+// threads, and should be quick about it.
+pub trait ProgressReporter: Send + Sync {
+    fn tile_done(&self, progress: &Progress);
+}
+
+impl<T: ProgressReporter + ?Sized> ProgressReporter for Arc<T> {
+    fn tile_done(&self, progress: &Progress) {
+        (**self).tile_done(progress)
+    }
+}
+
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn tile_done(&self, _progress: &Progress) {}
+}
+
+// Counts the tiles done by all the render threads
+pub struct ProgressCounter {
+    start: Instant,
+    tiles_total: usize,
+    tiles_done: AtomicUsize,
+    rays: AtomicU64,
+}
+
+impl ProgressCounter {
+    pub fn new(tiles_total: usize) -> Self {
+        ProgressCounter {
+            start: Instant::now(),
+            tiles_total,
+            tiles_done: AtomicUsize::new(0),
+            rays: AtomicU64::new(0),
+        }
+    }
+
+    pub fn tile_done(&self, rays: u64) -> Progress {
+        let tiles_done = self.tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+        let rays = self.rays.fetch_add(rays, Ordering::Relaxed) + rays;
+        Progress {
+            tiles_done,
+            tiles_total: self.tiles_total,
+            rays,
+            elapsed: self.start.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress {
+            tiles_done: 10,
+            tiles_total: 40,
+            rays: 5_000,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(progress.fraction(), 0.25);
+        assert_eq!(progress.rays_per_second(), 2_500.);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+
+        let counter = ProgressCounter::new(3);
+        assert_eq!(counter.tile_done(100).tiles_done, 1);
+        let progress = counter.tile_done(50);
+        assert_eq!((progress.tiles_done, progress.rays), (2, 150));
+        assert_eq!(
+            Progress {
+                tiles_done: 0,
+                ..progress
+            }
+            .eta(),
+            None
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, Light};
+use crate::hittable_list::HittableList;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rng::SampleRng;
+use crate::triangle::BBOX_PADDING;
+use crate::vec::{Onb, Point3, Vec3};
+
+use rand::Rng;
+use std::sync::Arc;
+
+// Ray parameter where the ray crosses the plane through `point` with the given normal
+fn intersect_plane(ray: &Ray, ray_t: Interval, point: Point3, normal: Vec3) -> Option<f64> {
+    let denominator = normal.dot(&ray.direction);
+    if denominator.abs() < 1e-12 {
+        // The ray is parallel to the plane
+        return None;
+    }
+    let t = normal.dot(&(point - ray.origin)) / denominator;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+    return Some(t);
+}
+
+// Parallelogram with a corner at `q` and sides `u` and `v`. Its front face is the one
+// where u turns counter-clockwise into v.
+pub struct Quad {
+    pub q: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    material: Arc<dyn Material>,
+    normal: Vec3,
+    // Maps a point of the plane, relative to q, to its (u, v) coordinates
+    w: Vec3,
+    area: f64,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = u.cross(&v);
+        Self {
+            q,
+            u,
+            v,
+            material,
+            normal: n.normalize(),
+            w: n / n.length_squared(),
+            area: n.length(),
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let t = intersect_plane(ray, ray_t, self.q, self.normal)?;
+        let p = ray.at(t);
+
+        let planar = p - self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+
+        let mut record = HitRecord {
+            t,
+            p,
+            u: alpha,
+            v: beta,
+            material: &*self.material,
+            ..Default::default()
+        };
+        record.set_face_normal(ray, self.normal);
+        return Some(record);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let diagonal = Aabb::from_points(self.q, self.q + self.u + self.v);
+        let other_diagonal = Aabb::from_points(self.q + self.u, self.q + self.v);
+        Aabb::enclosing(&diagonal, &other_diagonal).pad_to_minimums(BBOX_PADDING)
+    }
+}
+
+impl Light for Quad {
+    // Points are uniform over the area, seen from either side
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let ray = Ray::new(*origin, *direction);
+        let Some(record) = self.hit(&ray, Interval::new(0.001, f64::INFINITY)) else {
+            return 0.;
+        };
+        let distance_squared = record.t * record.t * direction.length_squared();
+        let cosine = direction.dot(&self.normal).abs() / direction.length();
+        return distance_squared / (cosine * self.area);
+    }
+
+    fn random(&self, rng: &mut SampleRng, origin: &Point3) -> Vec3 {
+        let p = self.q + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v;
+        return p - *origin;
+    }
+}
+
+// Infinite plane through `point`, its front face is on the side the normal points to
+pub struct Plane {
+    pub point: Point3,
+    normal: Vec3,
+    // Directions of the surface coordinates, which are distances along the plane
+    frame: Onb,
+    material: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, material: Arc<dyn Material>) -> Self {
+        let normal = normal.normalize();
+        Self {
+            point,
+            normal,
+            frame: Onb::new(normal),
+            material,
+        }
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let t = intersect_plane(ray, ray_t, self.point, self.normal)?;
+        let p = ray.at(t);
+        let planar = p - self.point;
+
+        let mut record = HitRecord {
+            t,
+            p,
+            u: planar.dot(&self.frame.u),
+            v: planar.dot(&self.frame.v),
+            material: &*self.material,
+            ..Default::default()
+        };
+        record.set_face_normal(ray, self.normal);
+        return Some(record);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::universe()
+    }
+}
+
+// Closed box with `a` and `b` as opposite corners, made of six quads facing outwards
+pub fn cuboid(a: Point3, b: Point3, material: Arc<dyn Material>) -> HittableList {
+    let min = Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+    let max = Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+    let dx = Vec3::new(max.x - min.x, 0., 0.);
+    let dy = Vec3::new(0., max.y - min.y, 0.);
+    let dz = Vec3::new(0., 0., max.z - min.z);
+
+    let faces = [
+        // Front and back
+        (Point3::new(min.x, min.y, max.z), dx, dy),
+        (Point3::new(max.x, min.y, min.z), -dx, dy),
+        // Right and left
+        (Point3::new(max.x, min.y, max.z), -dz, dy),
+        (Point3::new(min.x, min.y, min.z), dz, dy),
+        // Top and bottom
+        (Point3::new(min.x, max.y, max.z), dx, -dz),
+        (Point3::new(min.x, min.y, min.z), dx, dz),
+    ];
+    let mut sides = HittableList::new();
+    for (q, u, v) in faces {
+        sides.add(Box::new(Quad::new(q, u, v, material.clone())));
+    }
+    return sides;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::tests::check_light_sampling_pdf;
+    use crate::material::MATERIAL_CONCRETE;
+
+    #[test]
+    fn test_quad() {
+        let quad = Quad::new(
+            Point3::new(0., 0., 0.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            MATERIAL_CONCRETE.clone(),
+        );
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        let ray = Ray::new(Point3::new(1.5, 0.25, 3.), Vec3::new(0., 0., -1.));
+        let record = quad.hit(&ray, ray_t).unwrap();
+        assert_eq!(record.t, 3.);
+        assert_eq!((record.u, record.v), (0.75, 0.25));
+        assert_eq!(record.normal, Vec3::new(0., 0., 1.));
+        assert!(record.front_face);
+
+        let behind = Ray::new(Point3::new(1.5, 0.25, -3.), Vec3::new(0., 0., 1.));
+        let record = quad.hit(&behind, ray_t).unwrap();
+        assert_eq!(record.normal, Vec3::new(0., 0., -1.));
+        assert!(!record.front_face);
+
+        let outside = Ray::new(Point3::new(2.5, 0.25, 3.), Vec3::new(0., 0., -1.));
+        assert!(quad.hit(&outside, ray_t).is_none());
+        let parallel = Ray::new(Point3::new(1., 0.5, 3.), Vec3::new(1., 0., 0.));
+        assert!(quad.hit(&parallel, ray_t).is_none());
+
+        // Flat boxes get some thickness
+        let bbox = quad.bounding_box();
+        assert_eq!((bbox.x.lower, bbox.x.upper), (0., 2.));
+        assert!(bbox.z.size() > 0.);
+    }
+
+    #[test]
+    fn test_light_sampling_pdf() {
+        let quad = Quad::new(
+            Point3::new(-1., 2., -1.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 0., 2.),
+            MATERIAL_CONCRETE.clone(),
+        );
+        check_light_sampling_pdf(&quad, &Point3::new(0.5, 0., 0.), 200_000, 0.04);
+    }
+
+    #[test]
+    fn test_plane() {
+        let plane = Plane::new(
+            Point3::new(0., -1., 0.),
+            Vec3::new(0., 2., 0.),
+            MATERIAL_CONCRETE.clone(),
+        );
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let ray = Ray::new(Point3::new(1e6, 4., -3e5), Vec3::new(0., -1., 0.));
+        let record = plane.hit(&ray, ray_t).unwrap();
+        assert_eq!(record.t, 5.);
+        assert_eq!(record.normal, Vec3::new(0., 1., 0.));
+        assert!(record.front_face);
+        assert!(plane.bounding_box().is_unbounded());
+
+        let upwards = Ray::new(Point3::new(0., 4., 0.), Vec3::new(0., 1., 0.));
+        assert!(plane.hit(&upwards, ray_t).is_none());
+    }
+
+    #[test]
+    fn test_cuboid_faces_outwards() {
+        let cuboid = cuboid(
+            Point3::new(1., 2., 3.),
+            Point3::new(-1., -2., -3.),
+            MATERIAL_CONCRETE.clone(),
+        );
+        assert_eq!(cuboid.objects.len(), 6);
+        let bbox = cuboid.bounding_box();
+        // Up to the padding of the top and bottom faces
+        assert!((bbox.y.lower + 2.).abs() < 1e-3 && (bbox.y.upper - 2.).abs() < 1e-3);
+
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let directions = [
+            Vec3::new(1., 0., 0.),
+            Vec3::new(-1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(0., -1., 0.),
+            Vec3::new(0., 0., 1.),
+            Vec3::new(0., 0., -1.),
+        ];
+        for direction in directions {
+            // From outside, the first hit is a front face with the normal against the ray
+            let ray = Ray::new(-10. * direction, direction);
+            let record = cuboid.hit(&ray, ray_t).unwrap();
+            assert!(record.front_face);
+            assert_eq!(record.normal, -direction);
+
+            // From inside, every face is a back face
+            let ray = Ray::new(Point3::zeros(), direction);
+            let record = cuboid.hit(&ray, ray_t).unwrap();
+            assert!(!record.front_face);
+        }
+    }
+}
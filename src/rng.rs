@@ -0,0 +1,99 @@
+use crate::ray::Ray;
+
+use rand::SeedableRng;
+
+// Random number generator used for every sample. It is cheap to create, so each camera sample
+// gets its own generator and renders don't depend on how the work is scheduled.
+pub type SampleRng = rand_pcg::Pcg64Mcg;
+
+// SplitMix64 finalizer, scrambles the bits of its input
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Generator for one sample of one pixel, fully determined by its arguments
+pub fn sample_rng(seed: u64, x: usize, y: usize, sample_index: u64) -> SampleRng {
+    let mut state = mix(seed);
+    for value in [x as u64, y as u64, sample_index] {
+        state = mix(state ^ value);
+    }
+    SampleRng::seed_from_u64(state)
+}
+
+// Scrambled bits of a few numbers, to tell objects apart
+pub fn hash_values(values: &[f64]) -> u64 {
+    values
+        .iter()
+        .fold(0, |state, value| mix(state ^ value.to_bits()))
+}
+
+// Scrambled bits of some bytes, the same from one run or build to the next
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let state = bytes.chunks(8).fold(0, |state, chunk| {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        mix(state ^ u64::from_le_bytes(word))
+    });
+    mix(state ^ bytes.len() as u64)
+}
+
+// Generator fully determined by a ray and a salt, for random decisions made while
+// intersecting, where no sample generator is at hand. The same ray always makes the same
+// decisions, even when it is tested several times against an object. Objects making
+// decisions along the same ray need different salts, or their decisions are the same.
+pub fn ray_rng(ray: &Ray, salt: u64) -> SampleRng {
+    let mut state = mix(salt ^ mix(ray.time.to_bits()));
+    for value in [ray.origin, ray.direction] {
+        for coordinate in [value.x, value.y, value.z] {
+            state = mix(state ^ coordinate.to_bits());
+        }
+    }
+    SampleRng::seed_from_u64(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Vec3;
+    use rand::Rng;
+
+    #[test]
+    fn test_sample_rng_is_deterministic() {
+        let a: [u64; 4] = sample_rng(7, 3, 4, 5).gen();
+        let b: [u64; 4] = sample_rng(7, 3, 4, 5).gen();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_ray_rng() {
+        let ray = Ray::new(Vec3::new(1., 2., 3.), Vec3::new(0., 0., -1.));
+        let a: u64 = ray_rng(&ray, 0).gen();
+        let b: u64 = ray_rng(&ray, 0).gen();
+        assert_eq!(a, b);
+        let moved = Ray::new(Vec3::new(1., 2., 3.000001), Vec3::new(0., 0., -1.));
+        let c: u64 = ray_rng(&moved, 0).gen();
+        assert_ne!(a, c);
+        let salted: u64 = ray_rng(&ray, hash_values(&[1.])).gen();
+        assert_ne!(a, salted);
+    }
+
+    #[test]
+    fn test_hash_bytes() {
+        assert_eq!(hash_bytes(b"scene"), hash_bytes(b"scene"));
+        assert_ne!(hash_bytes(b"scene"), hash_bytes(b"scenf"));
+        // Trailing zeros change the length
+        assert_ne!(hash_bytes(b"scene"), hash_bytes(b"scene\0"));
+    }
+
+    #[test]
+    fn test_sample_rng_streams_differ() {
+        let reference: u64 = sample_rng(7, 3, 4, 5).gen();
+        for (seed, x, y, sample_index) in [(8, 3, 4, 5), (7, 4, 3, 5), (7, 3, 4, 6), (7, 4, 4, 5)] {
+            let other: u64 = sample_rng(seed, x, y, sample_index).gen();
+            assert_ne!(reference, other);
+        }
+    }
+}
@@ -0,0 +1,1245 @@
+use crate::background::{Background, EnvironmentMap, GradientBackground, SolidBackground};
+use crate::camera::Camera;
+use crate::hittable::{Hittable, Light};
+use crate::hittable_list::HittableList;
+use crate::instance::Instance;
+use crate::material::{
+    self, Dielectric, DiffuseLight, FresnelModel, HenyeyGreenstein, Isotropic, Lambertian,
+    Material, Metal,
+};
+use crate::matrix::Matrix4;
+use crate::medium::ConstantMedium;
+use crate::obj;
+use crate::quad::{cuboid, Plane, Quad};
+use crate::rng;
+use crate::sphere::{MovingSphere, Sphere};
+use crate::texture::{CheckerTexture, ImageTexture, NoiseStyle, NoiseTexture, SolidColor, Texture};
+use crate::triangle::Triangle;
+use crate::vec::Vec3;
+
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use toml::Spanned;
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: HittableList,
+    // Presets and the materials of the scene file, by name
+    pub materials: BTreeMap<String, Arc<dyn Material>>,
+}
+
+#[derive(Debug)]
+pub struct SceneError {
+    // 1-based position of the offending value in the scene file, if known
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "line {}, column {}: {}", line, column, self.message)
+            }
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(error: std::io::Error) -> Self {
+        SceneError {
+            line: None,
+            column: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl SceneError {
+    fn at(source: &str, span: Range<usize>, message: String) -> Self {
+        let before = &source[..span.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        SceneError {
+            line: Some(line),
+            column: Some(column),
+            message,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default)]
+    camera: CameraDesc,
+    background: Option<Spanned<BackgroundDesc>>,
+    #[serde(default)]
+    textures: BTreeMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: BTreeMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectDesc>>,
+    // Emitting objects, part of the world like the others but also sampled directly
+    #[serde(default)]
+    lights: Vec<Spanned<ObjectDesc>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    aspect_ratio: Option<Spanned<f64>>,
+    image_width: Option<Spanned<usize>>,
+    samples_per_pixel: Option<Spanned<i32>>,
+    min_samples_per_pixel: Option<Spanned<i32>>,
+    // Adaptive sampling is off unless given
+    noise_threshold: Option<Spanned<f64>>,
+    // 0 for no limit, paths are ended by Russian roulette
+    max_depth: Option<u32>,
+    russian_roulette_depth: Option<u32>,
+    vfov: Option<Spanned<f64>>,
+    lookfrom: Option<Spanned<[f64; 3]>>,
+    lookat: Option<Spanned<[f64; 3]>>,
+    v_up: Option<Spanned<[f64; 3]>>,
+    defocus_angle: Option<Spanned<f64>>,
+    focus_dist: Option<Spanned<f64>>,
+    shutter_open: Option<f64>,
+    shutter_close: Option<Spanned<f64>>,
+    seed: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackgroundDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    color: Option<[f64; 3]>,
+    bottom: Option<[f64; 3]>,
+    top: Option<[f64; 3]>,
+    // Radiance .hdr image, relative to the scene file
+    path: Option<Spanned<String>>,
+    intensity: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    color: Option<[f64; 3]>,
+    scale: Option<Spanned<f64>>,
+    even: Option<[f64; 3]>,
+    odd: Option<[f64; 3]>,
+    // PNG or PPM image, relative to the scene file
+    path: Option<Spanned<String>>,
+    style: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    albedo: Option<[f64; 3]>,
+    // Name of a texture, instead of a constant albedo
+    texture: Option<Spanned<String>>,
+    roughness: Option<Spanned<f64>>,
+    refraction_index: Option<Spanned<f64>>,
+    fresnel: Option<Spanned<String>>,
+    emit: Option<[f64; 3]>,
+    // Henyey-Greenstein parameter of isotropic materials, 0 by default
+    anisotropy: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    // Optional for meshes, which can use the materials of their MTL files
+    material: Option<Spanned<String>>,
+    center: Option<[f64; 3]>,
+    radius: Option<Spanned<f64>>,
+    // Moving spheres go from `center` at `time0` to `center1` at `time1`
+    center1: Option<[f64; 3]>,
+    time0: Option<f64>,
+    time1: Option<f64>,
+    vertices: Option<[[f64; 3]; 3]>,
+    // Quads span `u` and `v` from `corner`, boxes go from `corner` to `opposite_corner`
+    corner: Option<[f64; 3]>,
+    u: Option<[f64; 3]>,
+    v: Option<[f64; 3]>,
+    opposite_corner: Option<[f64; 3]>,
+    // Infinite planes through `point`, facing the side `normal` points to
+    point: Option<[f64; 3]>,
+    normal: Option<Spanned<[f64; 3]>>,
+    // Wavefront .obj file, relative to the scene file
+    path: Option<Spanned<String>>,
+    // Any object can be scaled, then rotated around x, y and z (in degrees), then translated
+    scale: Option<Spanned<[f64; 3]>>,
+    rotate: Option<[f64; 3]>,
+    translate: Option<[f64; 3]>,
+    // Fills the object with a participating medium, its material being the phase function
+    density: Option<Spanned<f64>>,
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+// Materials that can be used in any scene without being declared
+fn preset_materials() -> HashMap<String, Arc<dyn Material>> {
+    let presets: [(&str, Arc<dyn Material>); 8] = [
+        ("concrete", material::MATERIAL_CONCRETE.clone()),
+        ("ground", material::MATERIAL_GROUND.clone()),
+        ("copper", material::MATERIAL_COPPER.clone()),
+        ("silver", material::MATERIAL_SILVER.clone()),
+        ("brushed_copper", material::MATERIAL_BRUSHED_COPPER.clone()),
+        ("brushed_silver", material::MATERIAL_BRUSHED_SILVER.clone()),
+        ("red_plastic", material::MATERIAL_RED_PLASTIC.clone()),
+        ("glass", material::MATERIAL_GLASS.clone()),
+    ];
+    presets
+        .into_iter()
+        .map(|(name, material)| (name.to_string(), material))
+        .collect()
+}
+
+// Path of a mesh and the name of the material overriding its own ones
+type MeshKey = (String, Option<String>);
+
+struct SceneParser<'a> {
+    source: &'a str,
+    // Directory that relative paths in the scene are resolved against
+    base_dir: &'a Path,
+    // Meshes already loaded, by path and material override, shared by all their instances
+    meshes: RefCell<HashMap<MeshKey, Arc<dyn Hittable>>>,
+}
+
+impl<'a> SceneParser<'a> {
+    fn error(&self, span: Range<usize>, message: String) -> SceneError {
+        SceneError::at(self.source, span, message)
+    }
+
+    fn required<T>(
+        &self,
+        value: Option<T>,
+        name: &str,
+        kind: &Spanned<String>,
+    ) -> Result<T, SceneError> {
+        value.ok_or_else(|| {
+            self.error(
+                kind.span(),
+                format!("missing field `{}` for {}", name, kind.get_ref()),
+            )
+        })
+    }
+
+    fn positive<T: PartialOrd + Default + fmt::Display>(
+        &self,
+        value: &Spanned<T>,
+        name: &str,
+    ) -> Result<(), SceneError> {
+        if *value.get_ref() > T::default() {
+            return Ok(());
+        }
+        Err(self.error(
+            value.span(),
+            format!("`{}` must be positive, got {}", name, value.get_ref()),
+        ))
+    }
+
+    fn non_negative(&self, value: &Spanned<f64>, name: &str) -> Result<(), SceneError> {
+        if *value.get_ref() >= 0. {
+            return Ok(());
+        }
+        Err(self.error(
+            value.span(),
+            format!("`{}` must not be negative, got {}", name, value.get_ref()),
+        ))
+    }
+
+    fn camera(&self, desc: &CameraDesc) -> Result<Camera, SceneError> {
+        let mut camera = Camera::default();
+        if let Some(aspect_ratio) = &desc.aspect_ratio {
+            self.positive(aspect_ratio, "aspect_ratio")?;
+            camera.aspect_ratio = *aspect_ratio.get_ref();
+        }
+        if let Some(image_width) = &desc.image_width {
+            self.positive(image_width, "image_width")?;
+            camera.image_width = *image_width.get_ref();
+        }
+        if let Some(samples_per_pixel) = &desc.samples_per_pixel {
+            self.positive(samples_per_pixel, "samples_per_pixel")?;
+            camera.num_samples_per_pixel = *samples_per_pixel.get_ref();
+        }
+        if let Some(min_samples_per_pixel) = &desc.min_samples_per_pixel {
+            self.positive(min_samples_per_pixel, "min_samples_per_pixel")?;
+            camera.min_samples_per_pixel = *min_samples_per_pixel.get_ref();
+        }
+        if let Some(noise_threshold) = &desc.noise_threshold {
+            self.non_negative(noise_threshold, "noise_threshold")?;
+            camera.noise_threshold = *noise_threshold.get_ref();
+        }
+        if let Some(max_depth) = desc.max_depth {
+            camera.max_depth = max_depth;
+        }
+        if let Some(russian_roulette_depth) = desc.russian_roulette_depth {
+            camera.russian_roulette_depth = russian_roulette_depth;
+        }
+        if let Some(vfov) = &desc.vfov {
+            if !(*vfov.get_ref() > 0. && *vfov.get_ref() < 180.) {
+                return Err(self.error(
+                    vfov.span(),
+                    format!(
+                        "`vfov` must be between 0 and 180 degrees, got {}",
+                        vfov.get_ref()
+                    ),
+                ));
+            }
+            camera.vfov = *vfov.get_ref();
+        }
+        if let Some(lookfrom) = &desc.lookfrom {
+            camera.lookfrom = vec3(*lookfrom.get_ref());
+        }
+        if let Some(lookat) = &desc.lookat {
+            camera.lookat = vec3(*lookat.get_ref());
+        }
+        if let Some(v_up) = &desc.v_up {
+            camera.v_up = vec3(*v_up.get_ref());
+        }
+        // The camera needs a view direction, and an up direction to turn around it
+        let view_span = desc.lookat.as_ref().or(desc.lookfrom.as_ref());
+        let view = camera.lookat - camera.lookfrom;
+        if let Some(span) = view_span.map(Spanned::span) {
+            if view.length_squared() == 0. {
+                return Err(self.error(
+                    span,
+                    "`lookfrom` and `lookat` must be different points".to_string(),
+                ));
+            }
+        }
+        if let Some(span) = desc.v_up.as_ref().or(view_span).map(Spanned::span) {
+            if view.cross(&camera.v_up).length() <= 1e-12 * view.length() * camera.v_up.length() {
+                return Err(self.error(
+                    span,
+                    "`v_up` must not be parallel to the view direction".to_string(),
+                ));
+            }
+        }
+        if let Some(defocus_angle) = &desc.defocus_angle {
+            if !(*defocus_angle.get_ref() >= 0. && *defocus_angle.get_ref() < 180.) {
+                return Err(self.error(
+                    defocus_angle.span(),
+                    format!(
+                        "`defocus_angle` must be between 0 and 180 degrees, got {}",
+                        defocus_angle.get_ref()
+                    ),
+                ));
+            }
+            camera.defocus_angle = *defocus_angle.get_ref();
+        }
+        if let Some(focus_dist) = &desc.focus_dist {
+            self.positive(focus_dist, "focus_dist")?;
+            camera.focus_dist = *focus_dist.get_ref();
+        }
+        if let Some(shutter_open) = desc.shutter_open {
+            camera.shutter_open = shutter_open;
+        }
+        if let Some(shutter_close) = &desc.shutter_close {
+            if *shutter_close.get_ref() < camera.shutter_open {
+                return Err(self.error(
+                    shutter_close.span(),
+                    format!(
+                        "`shutter_close` must not be before `shutter_open`, got {} < {}",
+                        shutter_close.get_ref(),
+                        camera.shutter_open
+                    ),
+                ));
+            }
+            camera.shutter_close = *shutter_close.get_ref();
+        }
+        if let Some(seed) = desc.seed {
+            camera.seed = seed;
+        }
+        return Ok(camera);
+    }
+
+    fn background(&self, desc: &BackgroundDesc) -> Result<Box<dyn Background>, SceneError> {
+        let kind = &desc.kind;
+        match kind.get_ref().as_str() {
+            "solid" => {
+                let color = self.required(desc.color, "color", kind)?;
+                return Ok(Box::new(SolidBackground::new(vec3(color))));
+            }
+            "gradient" => {
+                let sky = GradientBackground::default();
+                let bottom = desc.bottom.map_or(sky.bottom, vec3);
+                let top = desc.top.map_or(sky.top, vec3);
+                return Ok(Box::new(GradientBackground::new(bottom, top)));
+            }
+            "environment_map" => {
+                let path = self.required(desc.path.as_ref(), "path", kind)?;
+                let mut map = EnvironmentMap::load_hdr(self.base_dir.join(path.get_ref()))
+                    .map_err(|error| {
+                        self.error(
+                            path.span(),
+                            format!("cannot load `{}`: {}", path.get_ref(), error),
+                        )
+                    })?;
+                if let Some(intensity) = desc.intensity {
+                    map.scale(intensity);
+                }
+                return Ok(Box::new(map));
+            }
+            other => {
+                return Err(self.error(kind.span(), format!("unknown background type `{}`", other)))
+            }
+        }
+    }
+
+    fn texture(&self, desc: &TextureDesc) -> Result<Arc<dyn Texture>, SceneError> {
+        let kind = &desc.kind;
+        let scale = match desc.scale.as_ref() {
+            None => 1.,
+            Some(scale) => {
+                self.positive(scale, "scale")?;
+                *scale.get_ref()
+            }
+        };
+        let texture: Arc<dyn Texture> = match kind.get_ref().as_str() {
+            "constant" => {
+                let color = self.required(desc.color, "color", kind)?;
+                Arc::new(SolidColor::new(vec3(color)))
+            }
+            "checker" => {
+                let even = self.required(desc.even, "even", kind)?;
+                let odd = self.required(desc.odd, "odd", kind)?;
+                Arc::new(CheckerTexture::from_colors(scale, vec3(even), vec3(odd)))
+            }
+            "image" => {
+                let path = self.required(desc.path.as_ref(), "path", kind)?;
+                let image =
+                    ImageTexture::load(self.base_dir.join(path.get_ref())).map_err(|error| {
+                        self.error(
+                            path.span(),
+                            format!("cannot load `{}`: {}", path.get_ref(), error),
+                        )
+                    })?;
+                Arc::new(image)
+            }
+            "noise" => {
+                let style = match desc.style.as_ref() {
+                    None => NoiseStyle::Smooth,
+                    Some(style) => match style.get_ref().as_str() {
+                        "smooth" => NoiseStyle::Smooth,
+                        "turbulence" => NoiseStyle::Turbulence,
+                        "marble" => NoiseStyle::Marble,
+                        other => {
+                            return Err(self
+                                .error(style.span(), format!("unknown noise style `{}`", other)))
+                        }
+                    },
+                };
+                Arc::new(NoiseTexture::new(scale, style))
+            }
+            other => {
+                return Err(self.error(kind.span(), format!("unknown texture type `{}`", other)))
+            }
+        };
+        return Ok(texture);
+    }
+
+    // Either a named texture or a constant `albedo`
+    fn albedo(
+        &self,
+        desc: &MaterialDesc,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        let kind = &desc.kind;
+        match &desc.texture {
+            None => {
+                let albedo = self.required(desc.albedo, "albedo", kind)?;
+                return Ok(Arc::new(SolidColor::new(vec3(albedo))));
+            }
+            Some(name) => {
+                if desc.albedo.is_some() {
+                    return Err(self.error(
+                        name.span(),
+                        "`albedo` and `texture` can't be used together".to_string(),
+                    ));
+                }
+                return textures.get(name.get_ref()).cloned().ok_or_else(|| {
+                    self.error(name.span(), format!("unknown texture `{}`", name.get_ref()))
+                });
+            }
+        }
+    }
+
+    fn material(
+        &self,
+        desc: &MaterialDesc,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        let kind = &desc.kind;
+        let material: Arc<dyn Material> = match kind.get_ref().as_str() {
+            "lambertian" => Arc::new(Lambertian::textured(self.albedo(desc, textures)?)),
+            "metal" => {
+                let albedo = self.albedo(desc, textures)?;
+                let roughness = match desc.roughness.as_ref() {
+                    None => 0.,
+                    Some(roughness) => {
+                        if !(0. ..=1.).contains(roughness.get_ref()) {
+                            return Err(self.error(
+                                roughness.span(),
+                                format!(
+                                    "`roughness` must be between 0 and 1, got {}",
+                                    roughness.get_ref()
+                                ),
+                            ));
+                        }
+                        *roughness.get_ref()
+                    }
+                };
+                Arc::new(Metal::textured(albedo, roughness))
+            }
+            "dielectric" => {
+                let refraction_index =
+                    self.required(desc.refraction_index.as_ref(), "refraction_index", kind)?;
+                self.positive(refraction_index, "refraction_index")?;
+                let fresnel = match desc.fresnel.as_ref() {
+                    None => FresnelModel::Schlick,
+                    Some(fresnel) => match fresnel.get_ref().as_str() {
+                        "schlick" => FresnelModel::Schlick,
+                        "exact" => FresnelModel::Exact,
+                        other => {
+                            return Err(self.error(
+                                fresnel.span(),
+                                format!("unknown fresnel model `{}`", other),
+                            ))
+                        }
+                    },
+                };
+                Arc::new(Dielectric::with_fresnel(
+                    *refraction_index.get_ref(),
+                    fresnel,
+                ))
+            }
+            "isotropic" => {
+                let albedo = self.albedo(desc, textures)?;
+                match desc.anisotropy.as_ref() {
+                    None => Arc::new(Isotropic::textured(albedo)),
+                    Some(anisotropy) => {
+                        if anisotropy.get_ref().abs() >= 1. {
+                            return Err(self.error(
+                                anisotropy.span(),
+                                format!(
+                                    "`anisotropy` must be between -1 and 1 excluded, got {}",
+                                    anisotropy.get_ref()
+                                ),
+                            ));
+                        }
+                        Arc::new(HenyeyGreenstein::textured(albedo, *anisotropy.get_ref()))
+                    }
+                }
+            }
+            "diffuse_light" => {
+                let emit = self.required(desc.emit, "emit", kind)?;
+                Arc::new(DiffuseLight::new(vec3(emit)))
+            }
+            other => {
+                return Err(self.error(kind.span(), format!("unknown material type `{}`", other)))
+            }
+        };
+        return Ok(material);
+    }
+
+    fn transform(&self, desc: &ObjectDesc) -> Result<Option<Matrix4>, SceneError> {
+        if desc.scale.is_none() && desc.rotate.is_none() && desc.translate.is_none() {
+            return Ok(None);
+        }
+        let mut transform = Matrix4::identity();
+        if let Some(scale) = &desc.scale {
+            transform = Matrix4::scaling(vec3(*scale.get_ref()));
+            // Rotations and translations can always be undone, scales flattening the object
+            // can't
+            if transform.inverse().is_none() {
+                return Err(self.error(
+                    scale.span(),
+                    format!(
+                        "`scale` must not flatten the object, got {:?}",
+                        scale.get_ref()
+                    ),
+                ));
+            }
+        }
+        if let Some([x, y, z]) = desc.rotate {
+            transform = Matrix4::rotation(Vec3::new(1., 0., 0.), x) * transform;
+            transform = Matrix4::rotation(Vec3::new(0., 1., 0.), y) * transform;
+            transform = Matrix4::rotation(Vec3::new(0., 0., 1.), z) * transform;
+        }
+        if let Some(translate) = desc.translate {
+            transform = Matrix4::translation(vec3(translate)) * transform;
+        }
+        return Ok(Some(transform));
+    }
+
+    fn object(
+        &self,
+        desc: &ObjectDesc,
+        materials: &HashMap<String, Arc<dyn Material>>,
+    ) -> Result<Box<dyn Hittable>, SceneError> {
+        let shape = self.shape(desc, materials)?;
+        let object: Box<dyn Hittable> = match self.transform(desc)? {
+            None => Box::new(shape),
+            Some(transform) => Box::new(
+                Instance::new(shape, transform)
+                    .map_err(|message| self.error(desc.kind.span(), message))?,
+            ),
+        };
+        let Some(density) = &desc.density else {
+            return Ok(object);
+        };
+        self.positive(density, "density")?;
+        let kind = &desc.kind;
+        let material = self.required(desc.material.as_ref(), "material", kind)?;
+        return Ok(Box::new(ConstantMedium::with_phase_function(
+            object,
+            *density.get_ref(),
+            materials[material.get_ref()].clone(),
+        )));
+    }
+
+    // Only shapes that can be sampled, as they are
+    fn light(
+        &self,
+        desc: &ObjectDesc,
+        materials: &HashMap<String, Arc<dyn Material>>,
+    ) -> Result<Arc<dyn Light>, SceneError> {
+        let kind = &desc.kind;
+        if !["sphere", "quad"].contains(&kind.get_ref().as_str()) {
+            return Err(self.error(
+                kind.span(),
+                format!("lights must be spheres or quads, not `{}`", kind.get_ref()),
+            ));
+        }
+        let transformed = desc.scale.is_some() || desc.rotate.is_some() || desc.translate.is_some();
+        if transformed || desc.density.is_some() {
+            return Err(self.error(
+                kind.span(),
+                "lights can't be transformed or filled with a medium".to_string(),
+            ));
+        }
+        let material = self.required(self.object_material(desc, materials)?, "material", kind)?;
+        let light = self.light_shape(desc, material)?;
+        return Ok(light.expect("Spheres and quads are lights"));
+    }
+
+    // Material of an object, None if it doesn't name one
+    fn object_material(
+        &self,
+        desc: &ObjectDesc,
+        materials: &HashMap<String, Arc<dyn Material>>,
+    ) -> Result<Option<Arc<dyn Material>>, SceneError> {
+        let Some(name) = &desc.material else {
+            return Ok(None);
+        };
+        let material = materials.get(name.get_ref()).cloned().ok_or_else(|| {
+            self.error(
+                name.span(),
+                format!("unknown material `{}`", name.get_ref()),
+            )
+        })?;
+        return Ok(Some(material));
+    }
+
+    // Spheres and quads, the shapes that can be sampled, None for the others
+    fn light_shape(
+        &self,
+        desc: &ObjectDesc,
+        material: Arc<dyn Material>,
+    ) -> Result<Option<Arc<dyn Light>>, SceneError> {
+        let kind = &desc.kind;
+        match kind.get_ref().as_str() {
+            "sphere" => {
+                let center = self.required(desc.center, "center", kind)?;
+                let radius = self.required(desc.radius.as_ref(), "radius", kind)?;
+                self.positive(radius, "radius")?;
+                return Ok(Some(Arc::new(Sphere::new(
+                    vec3(center),
+                    *radius.get_ref(),
+                    material,
+                ))));
+            }
+            "quad" => {
+                let corner = self.required(desc.corner, "corner", kind)?;
+                let u = vec3(self.required(desc.u, "u", kind)?);
+                let v = vec3(self.required(desc.v, "v", kind)?);
+                if u.cross(&v).length_squared() == 0. {
+                    return Err(self.error(
+                        kind.span(),
+                        "`u` and `v` of a quad must not be parallel".to_string(),
+                    ));
+                }
+                return Ok(Some(Arc::new(Quad::new(vec3(corner), u, v, material))));
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    // Object before its transform
+    fn shape(
+        &self,
+        desc: &ObjectDesc,
+        materials: &HashMap<String, Arc<dyn Material>>,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        let kind = &desc.kind;
+        let material = self.object_material(desc, materials)?;
+
+        if kind.get_ref() == "mesh" {
+            let path = self.required(desc.path.as_ref(), "path", kind)?;
+            let key = (
+                path.get_ref().clone(),
+                desc.material.as_ref().map(|name| name.get_ref().clone()),
+            );
+            if let Some(meshes) = self.meshes.borrow().get(&key) {
+                return Ok(meshes.clone());
+            }
+            let meshes: Arc<dyn Hittable> = Arc::new(
+                obj::load_obj(self.base_dir.join(path.get_ref()), material)
+                    .map_err(|error| self.error(path.span(), error.to_string()))?,
+            );
+            self.meshes.borrow_mut().insert(key, meshes.clone());
+            return Ok(meshes);
+        }
+
+        let material = self.required(material, "material", kind)?;
+        if let Some(light) = self.light_shape(desc, material.clone())? {
+            return Ok(light);
+        }
+        match kind.get_ref().as_str() {
+            "moving_sphere" => {
+                let center0 = self.required(desc.center, "center", kind)?;
+                let center1 = self.required(desc.center1, "center1", kind)?;
+                let radius = self.required(desc.radius.as_ref(), "radius", kind)?;
+                self.positive(radius, "radius")?;
+                return Ok(Arc::new(MovingSphere::new(
+                    vec3(center0),
+                    desc.time0.unwrap_or(0.),
+                    vec3(center1),
+                    desc.time1.unwrap_or(1.),
+                    *radius.get_ref(),
+                    material,
+                )));
+            }
+            "triangle" => {
+                let [v0, v1, v2] = self.required(desc.vertices, "vertices", kind)?;
+                return Ok(Arc::new(Triangle::new(
+                    vec3(v0),
+                    vec3(v1),
+                    vec3(v2),
+                    material,
+                )));
+            }
+            "plane" => {
+                let point = self.required(desc.point, "point", kind)?;
+                let normal = self.required(desc.normal.as_ref(), "normal", kind)?;
+                if vec3(*normal.get_ref()).length_squared() == 0. {
+                    return Err(self.error(normal.span(), "`normal` must not be zero".to_string()));
+                }
+                return Ok(Arc::new(Plane::new(
+                    vec3(point),
+                    vec3(*normal.get_ref()),
+                    material,
+                )));
+            }
+            "box" => {
+                let corner = self.required(desc.corner, "corner", kind)?;
+                let opposite = self.required(desc.opposite_corner, "opposite_corner", kind)?;
+                return Ok(Arc::new(cuboid(vec3(corner), vec3(opposite), material)));
+            }
+            other => {
+                return Err(self.error(kind.span(), format!("unknown object type `{}`", other)))
+            }
+        }
+    }
+
+    fn scene(&self) -> Result<Scene, SceneError> {
+        let desc: SceneDesc = toml::from_str(self.source).map_err(|error| {
+            let message = error.message().to_string();
+            match error.span() {
+                Some(span) => self.error(span, message),
+                None => SceneError {
+                    line: None,
+                    column: None,
+                    message,
+                },
+            }
+        })?;
+
+        let mut camera = self.camera(&desc.camera)?;
+        if let Some(background) = &desc.background {
+            camera.background = self.background(background.get_ref())?;
+        }
+
+        let mut textures = HashMap::new();
+        for (name, texture) in &desc.textures {
+            textures.insert(name.clone(), self.texture(texture.get_ref())?);
+        }
+
+        let mut materials = preset_materials();
+        for (name, material) in &desc.materials {
+            materials.insert(name.clone(), self.material(material.get_ref(), &textures)?);
+        }
+
+        camera.scene_hash = rng::hash_bytes(self.source.as_bytes());
+
+        let mut world = HittableList::new();
+        for object in &desc.objects {
+            world.add(self.object(object.get_ref(), &materials)?);
+        }
+        for light in &desc.lights {
+            let light = self.light(light.get_ref(), &materials)?;
+            world.add(Box::new(light.clone()));
+            camera.lights.add(Box::new(light));
+        }
+
+        return Ok(Scene {
+            camera,
+            world,
+            materials: materials.into_iter().collect(),
+        });
+    }
+}
+
+// Relative paths in the scene are resolved against the current directory
+pub fn parse_scene(source: &str) -> Result<Scene, SceneError> {
+    SceneParser {
+        source,
+        base_dir: Path::new(""),
+        meshes: RefCell::default(),
+    }
+    .scene()
+}
+
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)?;
+    SceneParser {
+        source: &source,
+        base_dir: path.parent().unwrap_or(Path::new("")),
+        meshes: RefCell::default(),
+    }
+    .scene()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+    use crate::rng::SampleRng;
+    use crate::vec::Point3;
+    use rand::SeedableRng;
+
+    fn parse_error(source: &str) -> SceneError {
+        match parse_scene(source) {
+            Ok(_) => panic!("Expected the scene to be rejected"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn test_parse_scene() {
+        let source = r#"
+[camera]
+image_width = 40
+samples_per_pixel = 3
+min_samples_per_pixel = 2
+noise_threshold = 0.01
+max_depth = 12
+russian_roulette_depth = 5
+lookfrom = [0, 0, 5]
+shutter_close = 0.5
+
+[materials.blue]
+type = "lambertian"
+albedo = [0.1, 0.2, 0.9]
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1.0
+material = "blue"
+
+[[objects]]
+type = "sphere"
+center = [0, -101, 0]
+radius = 100.0
+material = "concrete"
+
+[[objects]]
+type = "triangle"
+vertices = [[-1, 2, 0], [1, 2, 0], [0, 3, 0]]
+material = "blue"
+
+[[objects]]
+type = "moving_sphere"
+center = [3, 0, 0]
+center1 = [3, 1, 0]
+radius = 0.5
+material = "glass"
+"#;
+        let scene = parse_scene(source).unwrap();
+        assert_eq!(scene.camera.image_width, 40);
+        assert_eq!(scene.camera.num_samples_per_pixel, 3);
+        assert_eq!(scene.camera.min_samples_per_pixel, 2);
+        assert_eq!(scene.camera.noise_threshold, 0.01);
+        assert_eq!(scene.camera.max_depth, 12);
+        assert_eq!(scene.camera.russian_roulette_depth, 5);
+        assert_eq!(scene.camera.lookfrom, Point3::new(0., 0., 5.));
+        assert_eq!(scene.camera.shutter_close, 0.5);
+        // Checkpoints tell scenes apart by their description
+        let other = parse_scene(&source.replace("0.5", "0.6")).unwrap();
+        assert_ne!(scene.camera.scene_hash, other.camera.scene_hash);
+        assert_eq!(scene.world.objects.len(), 4);
+
+        let ray = Ray::new(Point3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let record = scene
+            .world
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert_eq!(record.t, 4.);
+    }
+
+    #[test]
+    fn test_unknown_material_reports_line() {
+        let source = "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1.0\nmaterial = \"unobtainium\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(5));
+        assert_eq!(error.column, Some(12));
+        assert!(error.message.contains("unobtainium"));
+    }
+
+    #[test]
+    fn test_bad_values_report_line() {
+        let source = "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = -1.0\nmaterial = \"glass\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(4));
+        assert!(error.message.contains("radius"));
+
+        let source = "[camera]\nimage_width = \"wide\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(2));
+
+        let source = "[camera]\nsamples_per_pixel = 64\nnoise_threshold = -0.01\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(3));
+        assert!(error.message.contains("noise_threshold"));
+        // 0 turns adaptive sampling off
+        let scene = parse_scene("[camera]\nnoise_threshold = 0.0\n").unwrap();
+        assert_eq!(scene.camera.noise_threshold, 0.);
+
+        // Cameras that can't see anything
+        for (source, line, name) in [
+            ("[camera]\nvfov = 180.0\n", 2, "vfov"),
+            ("[camera]\nvfov = 0\n", 2, "vfov"),
+            ("[camera]\nfocus_dist = 0.0\n", 2, "focus_dist"),
+            ("[camera]\ndefocus_angle = -1.0\n", 2, "defocus_angle"),
+            (
+                "[camera]\nlookfrom = [1, 2, 3]\nlookat = [1, 2, 3]\n",
+                3,
+                "lookat",
+            ),
+            (
+                "[camera]\nlookfrom = [0, 0, 0]\n\nlookat = [0, 0, -1]\nv_up = [0, 0, 2]\n",
+                5,
+                "v_up",
+            ),
+            ("[camera]\nlookat = [0, 5, 0]\n", 2, "v_up"),
+            (
+                "[camera]\nshutter_open = 0.5\nshutter_close = 0.25\n",
+                3,
+                "shutter_close",
+            ),
+        ] {
+            let error = parse_error(source);
+            assert_eq!(error.line, Some(line), "{}", source);
+            assert!(error.message.contains(name), "{}", error.message);
+        }
+
+        let source = "[materials.mystery]\ntype = \"plasma\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(2));
+        assert!(error.message.contains("plasma"));
+
+        let source = "[background]\ntype = \"environment_map\"\npath = \"missing.hdr\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(3));
+        assert!(error.message.contains("missing.hdr"));
+
+        let source = "[materials.shiny]\ntype = \"metal\"\n";
+        let error = parse_error(source);
+        assert!(error.message.contains("albedo"));
+        assert_eq!(
+            error.to_string(),
+            format!("line 2, column 8: {}", error.message)
+        );
+    }
+
+    #[test]
+    fn test_textures() {
+        let source = r#"
+[textures.checker]
+type = "checker"
+scale = 0.5
+even = [0.2, 0.3, 0.1]
+odd = [0.9, 0.9, 0.9]
+
+[textures.marble]
+type = "noise"
+scale = 4.0
+style = "marble"
+
+[materials.floor]
+type = "lambertian"
+texture = "checker"
+
+[materials.polished]
+type = "metal"
+texture = "marble"
+roughness = 0.2
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1.0
+material = "floor"
+"#;
+        let scene = parse_scene(source).unwrap();
+        let ray = Ray::new(Point3::new(0.25, 0.25, 5.), Vec3::new(0., 0., -1.));
+        let record = scene
+            .world
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        let mut rng = SampleRng::seed_from_u64(0);
+        let (attenuation, _) = record.material.scatter(&mut rng, &ray, &record).unwrap();
+        // (0.25, 0.25, 0.97) is in an odd cube
+        assert_eq!(attenuation, Vec3::new(0.9, 0.9, 0.9));
+
+        let source = "[materials.floor]\ntype = \"lambertian\"\ntexture = \"plaid\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(3));
+        assert!(error.message.contains("plaid"));
+
+        let source = "[textures.wood]\ntype = \"noise\"\nstyle = \"wood\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(3));
+
+        let source = "[textures.photo]\ntype = \"image\"\npath = \"missing.png\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(3));
+        assert!(error.message.contains("missing.png"));
+    }
+
+    #[test]
+    fn test_transforms_and_instances() {
+        let source = r#"
+[[objects]]
+type = "mesh"
+path = "assets/pyramid.obj"
+
+[[objects]]
+type = "mesh"
+path = "assets/pyramid.obj"
+scale = [2, 2, 2]
+rotate = [0, 45, 0]
+translate = [10, 0, 0]
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1.0
+material = "glass"
+scale = [1, 3, 1]
+translate = [0, 0, -10]
+"#;
+        let base_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let parser = SceneParser {
+            source,
+            base_dir: &base_dir,
+            meshes: RefCell::default(),
+        };
+        let scene = parser.scene().unwrap();
+        assert_eq!(scene.world.objects.len(), 3);
+        // Both pyramids share the same triangles
+        assert_eq!(parser.meshes.borrow().len(), 1);
+
+        // The apex of the big pyramid is at twice the height
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let ray = Ray::new(Point3::new(10., 10., 0.), Vec3::new(0., -1., 0.));
+        let record = scene.world.hit(&ray, ray_t).unwrap();
+        assert!((record.p.y - 3.).abs() < 1e-9);
+
+        let ray = Ray::new(Point3::new(0., 10., -10.), Vec3::new(0., -1., 0.));
+        let record = scene.world.hit(&ray, ray_t).unwrap();
+        assert!((record.p.y - 3.).abs() < 1e-9);
+
+        for scale in ["[1, 0, 1]", "[1e-13, 1, 1]"] {
+            let source = format!("[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1.0\nmaterial = \"glass\"\nscale = {}\n", scale);
+            let error = parse_error(&source);
+            assert_eq!(error.line, Some(6));
+            assert!(error.message.contains("scale"));
+        }
+    }
+
+    #[test]
+    fn test_media() {
+        let source = r#"
+[materials.smoke]
+type = "isotropic"
+albedo = [0.5, 0.5, 0.5]
+anisotropy = -0.3
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1.0
+material = "smoke"
+density = 1000.0
+"#;
+        let scene = parse_scene(source).unwrap();
+        let ray = Ray::new(Point3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let record = scene
+            .world
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        // Dense enough to scatter right after entering
+        assert!(record.t > 4. && record.t < 4.1);
+
+        let source =
+            "[materials.smoke]\ntype = \"isotropic\"\nalbedo = [1, 1, 1]\nanisotropy = 1.0\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(4));
+
+        let source = "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1.0\nmaterial = \"glass\"\ndensity = 0.0\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(6));
+        assert!(error.message.contains("density"));
+    }
+
+    #[test]
+    fn test_flat_shapes() {
+        let source = r#"
+[[objects]]
+type = "plane"
+point = [0, -1, 0]
+normal = [0, 1, 0]
+material = "concrete"
+
+[[objects]]
+type = "quad"
+corner = [-1, 0, -5]
+u = [2, 0, 0]
+v = [0, 2, 0]
+material = "copper"
+
+[[objects]]
+type = "box"
+corner = [4, 0, 1]
+opposite_corner = [2, 2, -1]
+material = "red_plastic"
+"#;
+        let scene = parse_scene(source).unwrap();
+        assert_eq!(scene.world.objects.len(), 3);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        // The plane goes on forever
+        let ray = Ray::new(Point3::new(1e5, 3., 1e5), Vec3::new(0., -1., 0.));
+        let record = scene.world.hit(&ray, ray_t).unwrap();
+        assert_eq!(record.p.y, -1.);
+
+        let ray = Ray::new(Point3::new(0., 1., 0.), Vec3::new(0., 0., -1.));
+        let record = scene.world.hit(&ray, ray_t).unwrap();
+        assert_eq!(record.t, 5.);
+
+        let ray = Ray::new(Point3::new(3., 10., 0.), Vec3::new(0., -1., 0.));
+        let record = scene.world.hit(&ray, ray_t).unwrap();
+        assert_eq!(record.p.y, 2.);
+        assert_eq!(record.normal, Vec3::new(0., 1., 0.));
+
+        let source = "[[objects]]\ntype = \"plane\"\npoint = [0, 0, 0]\nnormal = [0, 0, 0]\nmaterial = \"glass\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(4));
+
+        let source = "[[objects]]\ntype = \"quad\"\ncorner = [0, 0, 0]\nu = [1, 0, 0]\nv = [2, 0, 0]\nmaterial = \"glass\"\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(2));
+    }
+
+    #[test]
+    fn test_lights() {
+        let source = r#"
+[materials.lamp]
+type = "diffuse_light"
+emit = [4, 4, 4]
+
+[[objects]]
+type = "sphere"
+center = [0, -100, 0]
+radius = 100.0
+material = "concrete"
+
+[[lights]]
+type = "quad"
+corner = [-1, 5, -1]
+u = [2, 0, 0]
+v = [0, 0, 2]
+material = "lamp"
+
+[[lights]]
+type = "sphere"
+center = [0, 10, 0]
+radius = 1.0
+material = "lamp"
+"#;
+        let scene = parse_scene(source).unwrap();
+        // Lights are part of the world too
+        assert_eq!(scene.world.objects.len(), 3);
+        assert_eq!(scene.camera.lights.objects.len(), 2);
+
+        let source = "[[lights]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1.0\nmaterial = \"glass\"\ntranslate = [0, 1, 0]\n";
+        let error = parse_error(source);
+        assert_eq!(error.line, Some(2));
+
+        let source = "[[lights]]\ntype = \"triangle\"\nvertices = [[0, 0, 0], [1, 0, 0], [0, 1, 0]]\nmaterial = \"glass\"\n";
+        let error = parse_error(source);
+        assert!(error.message.contains("spheres or quads"));
+    }
+
+    #[test]
+    fn test_bundled_scenes_load() {
+        let scenes_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        for entry in std::fs::read_dir(scenes_dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                if let Err(error) = load_scene(&path) {
+                    panic!("{}: {}", path.display(), error);
+                }
+            }
+        }
+    }
+}
@@ -1,21 +1,25 @@
-use crate::hittable::{HitRecord, Hittable};
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, Light};
 use crate::interval::Interval;
 use crate::material;
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec::Point3;
+use crate::rng::SampleRng;
+use crate::vec::{Onb, Point3, Vec3};
 
 use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
-    material: &'static dyn Material,
+    material: Arc<dyn Material>,
 }
 
 impl Sphere {
     // TODO: Check that radius is > 0
-    pub fn new(center: Point3, radius: f64, material: &'static dyn Material) -> Self {
+    pub fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Self {
         Self {
             center,
             radius,
@@ -23,46 +27,235 @@ impl Sphere {
         }
     }
 
-    pub fn random(rng: &mut rand::rngs::ThreadRng) -> Self {
-        let material = &*material::random_static_material(rng);
+    pub fn random(rng: &mut SampleRng) -> Self {
+        let material = material::random_material(rng);
         let center = Point3::random(rng);
         let radius = rng.gen_range(0.0..1.0);
 
         Sphere {
             center: (center),
             radius: (radius),
-            material: (material),
+            material,
         }
     }
+
+    // Surface coordinates of a point on the unit sphere: u is the angle around the y axis
+    // starting from -x, v goes from the bottom pole to the top one
+    fn uv(p: &Point3) -> (f64, f64) {
+        let theta = (-p.y).clamp(-1., 1.).acos();
+        let phi = (-p.z).atan2(p.x) + PI;
+        return (phi / (2. * PI), theta / PI);
+    }
 }
 
-impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let ray_origin_to_center = ray.origin - self.center;
-        let a = ray.direction.length_squared();
-        let half_b = ray_origin_to_center.dot(&ray.direction);
-        let c = ray_origin_to_center.length_squared() - self.radius.powi(2);
-        let discriminant = half_b.powi(2) - a * c;
-
-        if discriminant < 0.0 {
+// Intersection with a sphere standing at `center`, shared by still and moving spheres
+fn hit_sphere<'a>(
+    center: Point3,
+    radius: f64,
+    material: &'a dyn Material,
+    ray: &Ray,
+    ray_t: Interval,
+) -> Option<HitRecord<'a>> {
+    let ray_origin_to_center = ray.origin - center;
+    let a = ray.direction.length_squared();
+    let half_b = ray_origin_to_center.dot(&ray.direction);
+    let c = ray_origin_to_center.length_squared() - radius.powi(2);
+    let discriminant = half_b.powi(2) - a * c;
+
+    if discriminant < 0.0 {
+        return None;
+    }
+    let discriminant_sqrt = discriminant.sqrt();
+    // Check first root
+    let mut t = (-half_b - discriminant_sqrt) / a;
+    if !ray_t.surrounds(t) {
+        // Check second root
+        t = (-half_b + discriminant_sqrt) / a;
+        if !ray_t.surrounds(t) {
             return None;
         }
-        let discriminant_sqrt = discriminant.sqrt();
-        // Check first root
-        let mut t = (-half_b - discriminant_sqrt) / a;
-        if !ray_t.surrounds(t) {
-            // Check second root
-            t = (-half_b + discriminant_sqrt) / a;
-            if !ray_t.surrounds(t) {
-                return None;
+    }
+    let mut record = HitRecord {
+        t,
+        p: ray.at(t),
+        material,
+        ..Default::default()
+    };
+    let outward_normal = (record.p - center) / radius;
+    record.set_face_normal(ray, outward_normal);
+    (record.u, record.v) = Sphere::uv(&outward_normal);
+    return Some(record);
+}
+
+fn sphere_bounding_box(center: Point3, radius: f64) -> Aabb {
+    let radius_vector = Vec3::new(radius, radius, radius);
+    Aabb::from_points(center - radius_vector, center + radius_vector)
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, &*self.material, ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        sphere_bounding_box(self.center, self.radius)
+    }
+}
+
+impl Light for Sphere {
+    // Directions are uniform in the cone of the sphere seen from the origin, which must be
+    // outside of it
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let ray = Ray::new(*origin, *direction);
+        if self
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .is_none()
+        {
+            return 0.;
+        }
+        let distance_squared = (self.center - *origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 0.;
+        }
+        let cos_theta_max = (1. - radius_squared / distance_squared).sqrt();
+        return 1. / (2. * PI * (1. - cos_theta_max));
+    }
+
+    fn random(&self, rng: &mut SampleRng, origin: &Point3) -> Vec3 {
+        let to_center = self.center - *origin;
+        let radius_squared = self.radius * self.radius;
+        let cos_theta_max = (1. - radius_squared / to_center.length_squared())
+            .max(0.)
+            .sqrt();
+        let cos_theta = 1. + rng.gen::<f64>() * (cos_theta_max - 1.);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f64>();
+        let frame = Onb::new(to_center.normalize());
+        return frame.to_world(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+    }
+}
+
+// Sphere moving in a straight line, from `center0` at `time0` to `center1` at `time1`. It
+// stays still before and after.
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(
+        center0: Point3,
+        time0: f64,
+        center1: Point3,
+        time1: f64,
+        radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material,
+        }
+    }
+
+    pub fn center(&self, time: f64) -> Point3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+        let fraction = ((time - self.time0) / (self.time1 - self.time0)).clamp(0., 1.);
+        return self.center0 + fraction * (self.center1 - self.center0);
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        hit_sphere(
+            self.center(ray.time),
+            self.radius,
+            &*self.material,
+            ray,
+            ray_t,
+        )
+    }
+
+    // The sphere never leaves its segment, so this covers any shutter interval
+    fn bounding_box(&self) -> Aabb {
+        Aabb::enclosing(
+            &sphere_bounding_box(self.center0, self.radius),
+            &sphere_bounding_box(self.center1, self.radius),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::tests::check_light_sampling_pdf;
+    use crate::material::MATERIAL_CONCRETE;
+
+    #[test]
+    fn test_light_sampling_pdf() {
+        let sphere = Sphere::new(Point3::new(0., 0., -4.), 1., MATERIAL_CONCRETE.clone());
+        check_light_sampling_pdf(&sphere, &Point3::new(0.5, 0., 0.), 1_000_000, 0.03);
+
+        // No cone to sample from the inside
+        assert_eq!(sphere.pdf_value(&sphere.center, &Vec3::new(0., 1., 0.)), 0.);
+    }
+
+    #[test]
+    fn test_uv() {
+        let close = |(u, v): (f64, f64), expected: (f64, f64)| {
+            (u - expected.0).abs() < 1e-12 && (v - expected.1).abs() < 1e-12
+        };
+        assert!(close(Sphere::uv(&Point3::new(1., 0., 0.)), (0.5, 0.5)));
+        assert!(close(Sphere::uv(&Point3::new(0., 1., 0.)), (0.5, 1.)));
+        assert!(close(Sphere::uv(&Point3::new(0., -1., 0.)), (0.5, 0.)));
+        assert!(close(Sphere::uv(&Point3::new(-1., 0., 0.)), (0., 0.5)));
+        assert!(close(Sphere::uv(&Point3::new(0., 0., 1.)), (0.25, 0.5)));
+        assert!(close(Sphere::uv(&Point3::new(0., 0., -1.)), (0.75, 0.5)));
+    }
+
+    #[test]
+    fn test_moving_sphere() {
+        let sphere = MovingSphere::new(
+            Point3::zeros(),
+            0.,
+            Point3::new(4., 0., 0.),
+            1.,
+            1.,
+            MATERIAL_CONCRETE.clone(),
+        );
+        assert_eq!(sphere.center(0.5), Point3::new(2., 0., 0.));
+        assert_eq!(sphere.center(-1.), Point3::zeros());
+        assert_eq!(sphere.center(3.), Point3::new(4., 0., 0.));
+
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let direction = Vec3::new(0., 0., -1.);
+        let early = Ray::with_time(Point3::new(0., 0., 5.), direction, 0.);
+        let late = Ray::with_time(Point3::new(0., 0., 5.), direction, 1.);
+        assert_eq!(sphere.hit(&early, ray_t).unwrap().t, 4.);
+        assert!(sphere.hit(&late, ray_t).is_none());
+
+        // The box covers the sphere at every instant
+        let bbox = sphere.bounding_box();
+        for time in [0., 0.25, 0.5, 0.75, 1.] {
+            let center = sphere.center(time);
+            for offset in [Vec3::new(1., 1., 1.), Vec3::new(-1., -1., -1.)] {
+                let p = center + offset;
+                assert!(bbox.x.contains(p.x) && bbox.y.contains(p.y) && bbox.z.contains(p.z));
             }
         }
-        let mut record = HitRecord::default();
-        record.t = t;
-        record.p = ray.at(t);
-        record.material = self.material;
-        let outward_normal = (record.p - self.center) / self.radius;
-        record.set_face_normal(ray, outward_normal);
-        return Some(record);
     }
 }
@@ -0,0 +1,184 @@
+use std::fmt;
+
+// Paths of this many bounces or more share the last bucket of the histogram
+pub const PATH_LENGTH_BUCKETS: usize = 32;
+
+// Why a path stopped bouncing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathEnd {
+    // Left the scene, towards the background
+    Escaped,
+    // Hit something that does not scatter, like a light
+    Absorbed,
+    RussianRoulette,
+    MaxDepth,
+}
+
+// Lengths of the paths traced during a render, a length being the number of bounces
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathStats {
+    pub histogram: [u64; PATH_LENGTH_BUCKETS],
+    pub total_bounces: u64,
+    pub longest: u32,
+    pub escaped: u64,
+    pub absorbed: u64,
+    pub russian_roulette: u64,
+    pub max_depth: u64,
+    // Rays testing whether a light or the background is visible, cast along the paths
+    pub shadow_rays: u64,
+}
+
+impl Default for PathStats {
+    fn default() -> Self {
+        PathStats {
+            histogram: [0; PATH_LENGTH_BUCKETS],
+            total_bounces: 0,
+            longest: 0,
+            escaped: 0,
+            absorbed: 0,
+            russian_roulette: 0,
+            max_depth: 0,
+            shadow_rays: 0,
+        }
+    }
+}
+
+impl PathStats {
+    pub fn add_path(&mut self, bounces: u32, end: PathEnd) {
+        self.histogram[(bounces as usize).min(PATH_LENGTH_BUCKETS - 1)] += 1;
+        self.total_bounces += bounces as u64;
+        self.longest = self.longest.max(bounces);
+        match end {
+            PathEnd::Escaped => self.escaped += 1,
+            PathEnd::Absorbed => self.absorbed += 1,
+            PathEnd::RussianRoulette => self.russian_roulette += 1,
+            PathEnd::MaxDepth => self.max_depth += 1,
+        }
+    }
+
+    pub fn merge(mut self, other: PathStats) -> PathStats {
+        for (count, other_count) in self.histogram.iter_mut().zip(other.histogram) {
+            *count += other_count;
+        }
+        self.total_bounces += other.total_bounces;
+        self.longest = self.longest.max(other.longest);
+        self.escaped += other.escaped;
+        self.absorbed += other.absorbed;
+        self.russian_roulette += other.russian_roulette;
+        self.max_depth += other.max_depth;
+        self.shadow_rays += other.shadow_rays;
+        return self;
+    }
+
+    pub fn paths(&self) -> u64 {
+        self.histogram.iter().sum()
+    }
+
+    pub fn mean_length(&self) -> f64 {
+        let paths = self.paths();
+        if paths == 0 {
+            return 0.;
+        }
+        return self.total_bounces as f64 / paths as f64;
+    }
+}
+
+impl fmt::Display for PathStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let paths = self.paths().max(1) as f64;
+        let percent = |count: u64| 100. * count as f64 / paths;
+        write!(
+            f,
+            "{} paths, {:.2} bounces on average, {} at most. Ended: {:.1}% escaped, {:.1}% absorbed, {:.1}% by Russian roulette, {:.1}% at max depth",
+            self.paths(),
+            self.mean_length(),
+            self.longest,
+            percent(self.escaped),
+            percent(self.absorbed),
+            percent(self.russian_roulette),
+            percent(self.max_depth)
+        )
+    }
+}
+
+// Mean and variance of a stream of values, updated one value at a time without keeping them
+// around (Welford, 1962)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RunningVariance {
+    pub count: u32,
+    pub mean: f64,
+    // Sum of the squared differences to the mean
+    pub m2: f64,
+}
+
+impl RunningVariance {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Unbiased sample variance, 0 until there are two values
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.;
+        }
+        return self.m2 / (self.count - 1) as f64;
+    }
+
+    // Standard deviation of the mean, how far it is likely to be from the true mean
+    pub fn standard_error(&self) -> f64 {
+        if self.count == 0 {
+            return 0.;
+        }
+        return (self.variance() / self.count as f64).sqrt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merged_stats() {
+        let mut a = PathStats::default();
+        a.add_path(0, PathEnd::Escaped);
+        a.add_path(3, PathEnd::RussianRoulette);
+        let mut b = PathStats::default();
+        b.add_path(100, PathEnd::MaxDepth);
+
+        let stats = a.merge(b);
+        assert_eq!(stats.paths(), 3);
+        assert_eq!(stats.longest, 100);
+        assert_eq!(stats.histogram[PATH_LENGTH_BUCKETS - 1], 1);
+        assert!((stats.mean_length() - 103. / 3.).abs() < 1e-12);
+        assert_eq!(
+            (stats.escaped, stats.russian_roulette, stats.max_depth),
+            (1, 1, 1)
+        );
+        assert_eq!(PathStats::default().mean_length(), 0.);
+    }
+
+    #[test]
+    fn test_running_variance() {
+        let values = [2., 4., 4., 4., 5., 5., 7., 9.];
+        let mut running = RunningVariance::default();
+        assert_eq!(running.variance(), 0.);
+        for value in values {
+            running.add(value);
+        }
+        assert_eq!(running.count, 8);
+        assert!((running.mean - 5.).abs() < 1e-12);
+        // Sum of squared differences is 32
+        assert!((running.variance() - 32. / 7.).abs() < 1e-12);
+        assert!((running.standard_error() - (4f64 / 7.).sqrt()).abs() < 1e-12);
+
+        // Large offsets don't wreck the precision like the sum of squares would
+        let mut shifted = RunningVariance::default();
+        for value in values {
+            shifted.add(value + 1e9);
+        }
+        assert!((shifted.variance() - 32. / 7.).abs() < 1e-6);
+    }
+}
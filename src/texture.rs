@@ -0,0 +1,312 @@
+use crate::color::{gamma_to_linear, Color};
+use crate::image_reader;
+use crate::rng::SampleRng;
+use crate::vec::{Point3, Vec3};
+
+use rand::{Rng, SeedableRng};
+use std::path::Path;
+use std::sync::Arc;
+
+// Color varying over a surface, looked up with the surface coordinates (u, v) of a hit and
+// its position p
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+pub struct SolidColor {
+    pub albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        SolidColor { albedo }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.albedo
+    }
+}
+
+// Alternates between two textures in a 3D grid of cubes of the given size
+pub struct CheckerTexture {
+    inv_scale: f64,
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        CheckerTexture {
+            inv_scale: 1. / scale,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> Self {
+        CheckerTexture::new(
+            scale,
+            Arc::new(SolidColor::new(even)),
+            Arc::new(SolidColor::new(odd)),
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let x = (self.inv_scale * p.x).floor() as i64;
+        let y = (self.inv_scale * p.y).floor() as i64;
+        let z = (self.inv_scale * p.z).floor() as i64;
+        if (x + y + z).rem_euclid(2) == 0 {
+            return self.even.value(u, v, p);
+        }
+        return self.odd.value(u, v, p);
+    }
+}
+
+// Image mapped over the surface coordinates, with v = 0 at the bottom of the image
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // Linear colors, row by row starting from the top left corner
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // PNG or PPM file, with gamma encoded colors like the ones we write
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let (width, height, pixels) = image_reader::read_image(path)?;
+        let pixels = pixels
+            .iter()
+            .map(|rgb| {
+                let [r, g, b] = rgb.map(|channel| gamma_to_linear(channel as f64 / 255.));
+                Color::new(r, g, b)
+            })
+            .collect();
+        return Ok(ImageTexture::new(width, height, pixels));
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        if self.pixels.is_empty() {
+            // Debugging cyan
+            return Color::new(0., 1., 1.);
+        }
+        let u = u.clamp(0., 1.);
+        let v = 1. - v.clamp(0., 1.);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        return self.pixels[j * self.width + i];
+    }
+}
+
+const PERLIN_POINT_COUNT: usize = 256;
+
+// Gradient noise on a lattice of random unit vectors (Perlin, "Improving Noise", 2002)
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutation_x: Vec<usize>,
+    permutation_y: Vec<usize>,
+    permutation_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut SampleRng) -> Self {
+        let gradients = (0..PERLIN_POINT_COUNT)
+            .map(|_| Vec3::random_unit_vector(rng))
+            .collect();
+        Perlin {
+            gradients,
+            permutation_x: Self::permutation(rng),
+            permutation_y: Self::permutation(rng),
+            permutation_z: Self::permutation(rng),
+        }
+    }
+
+    fn permutation(rng: &mut SampleRng) -> Vec<usize> {
+        let mut permutation: Vec<usize> = (0..PERLIN_POINT_COUNT).collect();
+        for i in (1..PERLIN_POINT_COUNT).rev() {
+            permutation.swap(i, rng.gen_range(0..=i));
+        }
+        return permutation;
+    }
+
+    // Smooth noise in [-1, 1]
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let floor = [p.x.floor(), p.y.floor(), p.z.floor()];
+        let fraction = [p.x - floor[0], p.y - floor[1], p.z - floor[2]];
+        // Hermite smoothing hides the lattice
+        let weight = fraction.map(|t| t * t * (3. - 2. * t));
+        let [i, j, k] = floor.map(|f| f as i64);
+
+        let mut accumulated = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.permutation_x[((i + di) & 255) as usize]
+                        ^ self.permutation_y[((j + dj) & 255) as usize]
+                        ^ self.permutation_z[((k + dk) & 255) as usize];
+                    let offset = Vec3::new(
+                        fraction[0] - di as f64,
+                        fraction[1] - dj as f64,
+                        fraction[2] - dk as f64,
+                    );
+                    let [wi, wj, wk] = [(di, 0), (dj, 1), (dk, 2)].map(|(d, axis)| {
+                        if d == 1 {
+                            weight[axis]
+                        } else {
+                            1. - weight[axis]
+                        }
+                    });
+                    accumulated += wi * wj * wk * self.gradients[index].dot(&offset);
+                }
+            }
+        }
+        return accumulated;
+    }
+
+    // Sum of noise octaves of halving amplitude and doubling frequency, up to the sum of the
+    // amplitudes, `turbulence_bound(depth)`
+    pub fn turbulence(&self, p: &Point3, depth: usize) -> f64 {
+        let mut accumulated = 0.;
+        let mut p = *p;
+        let mut weight = 1.;
+        for _ in 0..depth {
+            accumulated += weight * self.noise(&p);
+            weight *= 0.5;
+            p = 2. * p;
+        }
+        return accumulated.abs();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseStyle {
+    // Plain Perlin noise
+    Smooth,
+    // Several octaves of noise, like a cloudy or dirty surface
+    Turbulence,
+    // Stripes along z distorted by turbulence
+    Marble,
+}
+
+const TURBULENCE_DEPTH: usize = 7;
+
+// Largest possible turbulence, the sum of the amplitudes of the octaves
+pub fn turbulence_bound(depth: usize) -> f64 {
+    (0..depth).map(|octave| 0.5f64.powi(octave as i32)).sum()
+}
+
+// Gray noise, `scale` is the frequency of the pattern
+pub struct NoiseTexture {
+    perlin: Perlin,
+    pub scale: f64,
+    pub style: NoiseStyle,
+}
+
+impl NoiseTexture {
+    // The lattice is always generated from the same seed, so renders are reproducible
+    pub fn new(scale: f64, style: NoiseStyle) -> Self {
+        let mut rng = SampleRng::seed_from_u64(0);
+        NoiseTexture {
+            perlin: Perlin::new(&mut rng),
+            scale,
+            style,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let scaled = self.scale * *p;
+        let intensity = match self.style {
+            NoiseStyle::Smooth => 0.5 * (1. + self.perlin.noise(&scaled)),
+            NoiseStyle::Turbulence => {
+                self.perlin.turbulence(&scaled, TURBULENCE_DEPTH)
+                    / turbulence_bound(TURBULENCE_DEPTH)
+            }
+            NoiseStyle::Marble => {
+                let turbulence = self.perlin.turbulence(p, TURBULENCE_DEPTH);
+                0.5 * (1. + (scaled.z + 10. * turbulence).sin())
+            }
+        };
+        return intensity * Color::white();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checker_alternates_in_3d() {
+        let even = Color::white();
+        let odd = Color::black();
+        let checker = CheckerTexture::from_colors(0.5, even, odd);
+        assert_eq!(checker.value(0., 0., &Point3::new(0.1, 0.1, 0.1)), even);
+        assert_eq!(checker.value(0., 0., &Point3::new(0.6, 0.1, 0.1)), odd);
+        assert_eq!(checker.value(0., 0., &Point3::new(0.6, 0.6, 0.1)), even);
+        // Negative coordinates don't mirror the pattern around the origin
+        assert_eq!(checker.value(0., 0., &Point3::new(-0.1, 0.1, 0.1)), odd);
+        assert_eq!(checker.value(0., 0., &Point3::new(-0.6, 0.1, 0.1)), even);
+    }
+
+    #[test]
+    fn test_image_texture_lookup() {
+        let red = Color::new(1., 0., 0.);
+        let green = Color::new(0., 1., 0.);
+        let blue = Color::new(0., 0., 1.);
+        let white = Color::white();
+        let image = ImageTexture::new(2, 2, vec![red, green, blue, white]);
+        let p = Point3::zeros();
+        // v goes up, rows go down
+        assert_eq!(image.value(0.25, 0.75, &p), red);
+        assert_eq!(image.value(0.75, 0.75, &p), green);
+        assert_eq!(image.value(0.25, 0.25, &p), blue);
+        assert_eq!(image.value(1., 0., &p), white);
+        // Out of range coordinates are clamped
+        assert_eq!(image.value(-3., 5., &p), red);
+    }
+
+    #[test]
+    fn test_noise_is_bounded_and_continuous() {
+        let mut rng = SampleRng::seed_from_u64(0);
+        let perlin = Perlin::new(&mut rng);
+        for _ in 0..1000 {
+            let p = Vec3::random_in_bounds(&mut rng, -50., 50.);
+            let noise = perlin.noise(&p);
+            assert!((-1. ..=1.).contains(&noise), "noise {}", noise);
+            let nearby = perlin.noise(&(p + Vec3::new(1e-6, 1e-6, 1e-6)));
+            assert!((noise - nearby).abs() < 1e-4);
+        }
+        // Gradient noise vanishes on the lattice
+        assert_eq!(perlin.noise(&Point3::new(3., -2., 7.)), 0.);
+
+        for style in [
+            NoiseStyle::Smooth,
+            NoiseStyle::Turbulence,
+            NoiseStyle::Marble,
+        ] {
+            // Surfaces can't reflect more light than they get
+            let texture = NoiseTexture::new(4., style);
+            for _ in 0..10_000 {
+                let p = Vec3::random_in_bounds(&mut rng, -20., 20.);
+                let color = texture.value(0., 0., &p);
+                assert!(color.x >= 0. && color.x <= 1., "{:?}: {}", style, color.x);
+            }
+        }
+        assert_eq!(turbulence_bound(3), 1.75);
+    }
+}
@@ -0,0 +1,159 @@
+// Block of pixels rendered in one go. Tiles are square, except for the last ones of each row
+// and column, which are cut by the edges of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Order in which the tiles of an image are rendered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    // Outwards from the center of the image, where the subject usually is
+    #[default]
+    Spiral,
+    // Along a Hilbert curve, tiles rendered one after the other are close in the image
+    Hilbert,
+}
+
+// Grid positions of a `columns` by `rows` grid, in a square spiral around the center
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let mut positions = Vec::with_capacity(columns * rows);
+    let (mut x, mut y) = (((columns - 1) / 2) as i64, ((rows - 1) / 2) as i64);
+    // Right, down, left, up, with the sides getting longer every other turn
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut side = 1;
+    let mut turn = 0;
+    while positions.len() < columns * rows {
+        let (dx, dy) = directions[turn % 4];
+        for _ in 0..side {
+            if (0..columns as i64).contains(&x) && (0..rows as i64).contains(&y) {
+                positions.push((x as usize, y as usize));
+            }
+            x += dx;
+            y += dy;
+        }
+        turn += 1;
+        if turn % 2 == 0 {
+            side += 1;
+        }
+    }
+    return positions;
+}
+
+// Position of the `d`-th point of the Hilbert curve filling an `n` by `n` grid, `n` being a
+// power of two
+fn hilbert_point(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        // Rotate the quadrant so that the curve joins up
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    return (x, y);
+}
+
+// Grid positions along the Hilbert curve of the smallest power of two grid that covers it
+fn hilbert(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let n = columns.max(rows).next_power_of_two();
+    (0..n * n)
+        .map(|d| hilbert_point(n, d))
+        .filter(|&(x, y)| x < columns && y < rows)
+        .collect()
+}
+
+// Tiles of `tile_size` pixels covering a `width` by `height` image, in rendering order
+pub fn tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let tile_size = tile_size.max(1);
+    let columns = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+    let positions = match order {
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => hilbert(columns, rows),
+    };
+    positions
+        .into_iter()
+        .map(|(column, row)| {
+            let (x, y) = (column * tile_size, row * tile_size);
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_cover_every_pixel_once() {
+        for order in [TileOrder::Spiral, TileOrder::Hilbert] {
+            for (width, height, tile_size) in [(100, 60, 16), (7, 30, 8), (1, 1, 32), (64, 64, 1)] {
+                let mut covered = vec![0; width * height];
+                for tile in tiles(width, height, tile_size, order) {
+                    assert!(tile.width <= tile_size && tile.height <= tile_size);
+                    for y in tile.y..tile.y + tile.height {
+                        for x in tile.x..tile.x + tile.width {
+                            covered[y * width + x] += 1;
+                        }
+                    }
+                }
+                assert!(covered.iter().all(|&count| count == 1), "{:?}", order);
+            }
+        }
+        assert!(tiles(0, 10, 4, TileOrder::Spiral).is_empty());
+    }
+
+    #[test]
+    fn test_tile_orders() {
+        // 5x3 tiles, starting from the middle one and going round it
+        let spiral: Vec<(usize, usize)> = tiles(50, 30, 10, TileOrder::Spiral)
+            .iter()
+            .map(|tile| (tile.x / 10, tile.y / 10))
+            .collect();
+        assert_eq!(
+            spiral[..9],
+            [
+                (2, 1),
+                (3, 1),
+                (3, 2),
+                (2, 2),
+                (1, 2),
+                (1, 1),
+                (1, 0),
+                (2, 0),
+                (3, 0)
+            ]
+        );
+
+        // Each tile of the Hilbert curve is next to the one before
+        let hilbert = tiles(64, 64, 8, TileOrder::Hilbert);
+        assert_eq!((hilbert[0].x, hilbert[0].y), (0, 0));
+        for pair in hilbert.windows(2) {
+            let distance = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(distance, 8);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::Point3;
+use std::sync::Arc;
+
+// Padding of the bounding boxes of flat objects
+pub(crate) const BBOX_PADDING: f64 = 1e-4;
+
+// Möller–Trumbore ray-triangle intersection. Returns the ray parameter and the barycentric
+// coordinates of the hit point along the (p0, p1) and (p0, p2) edges.
+pub fn intersect_triangle(
+    ray: &Ray,
+    ray_t: Interval,
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let p_vec = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p_vec);
+    if determinant.abs() < 1e-12 {
+        // The ray is parallel to the triangle
+        return None;
+    }
+    let inverse_determinant = 1. / determinant;
+
+    let t_vec = ray.origin - p0;
+    let b1 = t_vec.dot(&p_vec) * inverse_determinant;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+
+    let q_vec = t_vec.cross(&edge1);
+    let b2 = ray.direction.dot(&q_vec) * inverse_determinant;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+
+    let t = edge2.dot(&q_vec) * inverse_determinant;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+    return Some((t, b1, b2));
+}
+
+// Flat shaded triangle, its front face is the one where the vertices are counter-clockwise
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices: [v0, v1, v2],
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let [p0, p1, p2] = self.vertices;
+        let (t, b1, b2) = intersect_triangle(ray, ray_t, p0, p1, p2)?;
+
+        let mut record = HitRecord {
+            t,
+            p: ray.at(t),
+            u: b1,
+            v: b2,
+            material: &*self.material,
+            ..Default::default()
+        };
+        let outward_normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+        record.set_face_normal(ray, outward_normal);
+        return Some(record);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [p0, p1, p2] = self.vertices;
+        Aabb::enclosing(&Aabb::from_points(p0, p1), &Aabb::from_points(p0, p2))
+            .pad_to_minimums(BBOX_PADDING)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MATERIAL_CONCRETE;
+    use crate::vec::Vec3;
+
+    fn unit_triangle() -> Triangle {
+        Triangle::new(
+            Point3::new(0., 0., 0.),
+            Point3::new(1., 0., 0.),
+            Point3::new(0., 1., 0.),
+            MATERIAL_CONCRETE.clone(),
+        )
+    }
+
+    #[test]
+    fn test_hit_inside() {
+        let triangle = unit_triangle();
+        let ray = Ray::new(Point3::new(0.25, 0.5, 2.), Vec3::new(0., 0., -1.));
+        let record = triangle
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((record.t - 2.).abs() < 1e-12);
+        assert!((record.u - 0.25).abs() < 1e-12);
+        assert!((record.v - 0.5).abs() < 1e-12);
+        assert!(record.front_face);
+        assert_eq!(record.normal, Vec3::new(0., 0., 1.));
+
+        // From behind
+        let ray = Ray::new(Point3::new(0.25, 0.5, -2.), Vec3::new(0., 0., 1.));
+        let record = triangle
+            .hit(&ray, Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!(!record.front_face);
+        assert_eq!(record.normal, Vec3::new(0., 0., -1.));
+    }
+
+    #[test]
+    fn test_miss() {
+        let triangle = unit_triangle();
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        // Outside of the hypotenuse
+        let ray = Ray::new(Point3::new(0.6, 0.6, 2.), Vec3::new(0., 0., -1.));
+        assert!(triangle.hit(&ray, ray_t).is_none());
+        // Parallel to the triangle
+        let ray = Ray::new(Point3::new(-1., 0.2, 0.), Vec3::new(1., 0., 0.));
+        assert!(triangle.hit(&ray, ray_t).is_none());
+        // Behind the origin of the ray
+        let ray = Ray::new(Point3::new(0.2, 0.2, -2.), Vec3::new(0., 0., -1.));
+        assert!(triangle.hit(&ray, ray_t).is_none());
+    }
+
+    #[test]
+    fn test_flat_bounding_box_is_padded() {
+        let bbox = unit_triangle().bounding_box();
+        assert!(bbox.z.size() > 0.);
+        let ray = Ray::new(Point3::new(0.25, 0.25, 2.), Vec3::new(0., 0., -1.));
+        assert!(bbox.hit(&ray, Interval::new(0.001, f64::INFINITY)));
+    }
+}
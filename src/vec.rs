@@ -1,3 +1,4 @@
+use crate::rng::SampleRng;
 use rand::Rng;
 use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
 
@@ -40,11 +41,11 @@ impl Vec3 {
         self.close_to_with_tol(other, tol)
     }
 
-    pub fn random(rng: &mut rand::rngs::ThreadRng) -> Vec3 {
+    pub fn random(rng: &mut SampleRng) -> Vec3 {
         Vec3::new(rng.gen(), rng.gen(), rng.gen())
     }
 
-    pub fn random_in_bounds(rng: &mut rand::rngs::ThreadRng, lower: f64, upper: f64) -> Vec3 {
+    pub fn random_in_bounds(rng: &mut SampleRng, lower: f64, upper: f64) -> Vec3 {
         Vec3::new(
             rng.gen_range(lower..upper),
             rng.gen_range(lower..upper),
@@ -52,7 +53,7 @@ impl Vec3 {
         )
     }
 
-    pub fn random_in_unit_ball(rng: &mut rand::rngs::ThreadRng) -> Vec3 {
+    pub fn random_in_unit_ball(rng: &mut SampleRng) -> Vec3 {
         loop {
             let candidate = Vec3::random_in_bounds(rng, -1., 1.);
             if candidate.length_squared() < 1. {
@@ -61,11 +62,11 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector(rng: &mut rand::rngs::ThreadRng) -> Vec3 {
+    pub fn random_unit_vector(rng: &mut SampleRng) -> Vec3 {
         Vec3::random_in_unit_ball(rng).normalize()
     }
 
-    pub fn random_in_hemisphere(rng: &mut rand::rngs::ThreadRng, normal: Vec3) -> Vec3 {
+    pub fn random_in_hemisphere(rng: &mut SampleRng, normal: Vec3) -> Vec3 {
         let in_unit_sphere = Vec3::random_in_unit_ball(rng);
         if in_unit_sphere.dot(&normal) > 0. {
             in_unit_sphere
@@ -74,7 +75,16 @@ impl Vec3 {
         }
     }
 
-    pub fn random_in_unit_disk(rng: &mut rand::rngs::ThreadRng) -> Vec3 {
+    // Direction in the hemisphere around +z, with a density proportional to its z coordinate
+    // (Malley's method)
+    pub fn random_cosine_direction(rng: &mut SampleRng) -> Vec3 {
+        let r_squared = rng.gen::<f64>();
+        let r = r_squared.sqrt();
+        let phi = 2. * std::f64::consts::PI * rng.gen::<f64>();
+        return Vec3::new(r * phi.cos(), r * phi.sin(), (1. - r_squared).sqrt());
+    }
+
+    pub fn random_in_unit_disk(rng: &mut SampleRng) -> Vec3 {
         loop {
             let candidate = Vec3::new(rng.gen_range(-1.0..1.), rng.gen_range(-1.0..1.), 0.);
             if candidate.length_squared() < 1. {
@@ -83,6 +93,10 @@ impl Vec3 {
         }
     }
 
+    pub fn max_component(&self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
+
     pub fn dot(&self, other: &Vec3) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
@@ -209,8 +223,37 @@ impl Div<f64> for Vec3 {
 
 pub type Point3 = Vec3;
 
-#[cfg(test)]
+// Orthonormal basis with w along a given unit vector, used to build local shading frames
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(w: Vec3) -> Self {
+        // Duff et al., "Building an Orthonormal Basis, Revisited"
+        let sign = 1f64.copysign(w.z);
+        let a = -1. / (sign + w.z);
+        let b = w.x * w.y * a;
+        let u = Vec3::new(1. + sign * w.x * w.x * a, sign * b, -sign * w.x);
+        let v = Vec3::new(b, sign + w.y * w.y * a, -w.y);
+        Onb { u, v, w }
+    }
+
+    // Coordinates in this basis to world coordinates
+    pub fn to_world(&self, local: Vec3) -> Vec3 {
+        local.x * self.u + local.y * self.v + local.z * self.w
+    }
+
+    // World coordinates to coordinates in this basis
+    pub fn to_local(&self, world: Vec3) -> Vec3 {
+        Vec3::new(world.dot(&self.u), world.dot(&self.v), world.dot(&self.w))
+    }
+}
 
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -312,8 +355,44 @@ mod tests {
         assert_eq!(result, Vec3::new(0.0, 0.0, 1.0));
         let dot_with_a = result.dot(&a);
         let dot_with_b = result.dot(&b);
-        assert!(dot_with_a.abs() < std::f64::EPSILON && dot_with_b.abs() < std::f64::EPSILON);
+        assert!(dot_with_a.abs() < f64::EPSILON && dot_with_b.abs() < f64::EPSILON);
+    }
+    #[test]
+    fn test_onb_is_orthonormal() {
+        for w in [
+            Vec3::new(0., 0., 1.),
+            Vec3::new(0., 0., -1.),
+            Vec3::new(1., 2., 3.).normalize(),
+            Vec3::new(-0.3, 0.1, -0.9).normalize(),
+        ] {
+            let onb = Onb::new(w);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+                assert!(a.dot(&b).abs() < 1e-12);
+                assert!((a.length() - 1.).abs() < 1e-12);
+            }
+            assert!(onb.u.cross(&onb.v).close_to_with_tol(w, 1e-12));
+
+            let p = Vec3::new(0.5, -2., 3.);
+            assert!(onb.to_world(onb.to_local(p)).close_to_with_tol(p, 1e-12));
+        }
+    }
+
+    #[test]
+    fn test_cosine_directions() {
+        use rand::SeedableRng;
+        let mut rng = SampleRng::seed_from_u64(0);
+        let n_directions = 100_000;
+        let mut total_z = 0.;
+        for _ in 0..n_directions {
+            let direction = Vec3::random_cosine_direction(&mut rng);
+            assert!((direction.length() - 1.).abs() < 1e-12);
+            assert!(direction.z > 0.);
+            total_z += direction.z;
+        }
+        // The average cosine over a cosine weighted hemisphere is 2/3
+        assert!((total_z / n_directions as f64 - 2. / 3.).abs() < 0.005);
     }
+
     #[test]
     fn test_cross_product_anticommutativity() {
         let a = Vec3::new(1.0, 2.0, 3.0);